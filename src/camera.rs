@@ -1,4 +1,5 @@
 use nalgebra as na;
+use std::f32::consts::PI;
 
 const FOV: f32 = 4.0;
 
@@ -21,7 +22,7 @@ impl Default for Camera {
 		Self {
 			width: Default::default(),
 			height: Default::default(),
-			projection: na::Perspective3::new(1.0, 3.14 / FOV, near, far),
+			projection: na::Perspective3::new(1.0, PI / FOV, near, far),
 			position: na::Point3::new(0.0, 0.0, 0.0),
 			rotation: na::Vector3::new(0.0, 0.0, 0.0),
 			scaling: na::Vector3::new(1.0, 1.0, 1.0),
@@ -36,7 +37,7 @@ impl Camera {
 		Self {
 			width,
 			height,
-			projection: na::Perspective3::new(width / height, 3.14 / FOV, 0.0001, 100.0),
+			projection: na::Perspective3::new(width / height, PI / FOV, 0.0001, 100.0),
 			..Default::default()
 		}
 	}
@@ -79,7 +80,7 @@ impl Camera {
 	pub fn resize(&mut self, width: f32, height: f32) {
 		self.width = width;
 		self.height = height;
-		let aspect = (3.14 * self.scaling.x) / (FOV * self.scaling.y);
+		let aspect = (PI * self.scaling.x) / (FOV * self.scaling.y);
 		self.projection = na::Perspective3::new(width / height, aspect, self.near, self.far);
 	}
 
@@ -1,79 +1,72 @@
-use crate::protos::vector_tile::Tile as VectorTile;
+use crate::error::Result;
 use crate::tile::Tile;
-use quick_protobuf::{MessageRead, Reader};
+use async_trait::async_trait;
 
-pub trait TileSource {
-	fn get_tile(&self, x: i32, y: i32, z: i32) -> Tile;
-}
-
-#[derive(Debug, Default)]
-pub struct WebTileSource {
-	token: String,
-}
+#[cfg(target_arch = "wasm32")]
+mod abort;
+mod cache;
+mod composite;
+pub mod decode;
+mod geojson;
+#[cfg(not(target_arch = "wasm32"))]
+mod mbtiles;
+pub mod pmtiles;
+mod range;
+mod raster;
+mod terrain;
+mod web;
 
-impl WebTileSource {
-	pub fn new(token: &str) -> Self {
-		Self { token: token.into() }
-	}
-}
-
-impl WebTileSource {
-	pub fn get_url(&self, x: i32, y: i32, z: i32) -> String {
-		format!(
-			"https://api.mapbox.com/v4/mapbox.mapbox-streets-v8/{}/{}/{}.vector.pbf?access_token={}",
-			z, x, y, self.token
-		)
-	}
+#[cfg(not(target_arch = "wasm32"))]
+pub use cache::DiskCacheStore;
+pub use cache::{CacheEntry, CacheStore, MemoryCacheStore, TileCache};
+pub use composite::CompositeSource;
+#[cfg(not(target_arch = "wasm32"))]
+pub use mbtiles::{MbTilesMetadata, MbTilesSource, VectorLayer};
+pub use geojson::GeoJsonSource;
+pub use pmtiles::PmTilesSource;
+#[cfg(not(target_arch = "wasm32"))]
+pub use range::FileRangeReader;
+#[cfg(target_arch = "wasm32")]
+pub use range::HttpRangeReader;
+pub use range::RangeReader;
+pub use raster::RasterSource;
+pub use terrain::TerrainSource;
+pub use web::WebTileSource;
 
-	#[cfg(target_arch = "wasm32")]
-	pub async fn get_tile(&self, x: i32, y: i32, z: i32) -> Tile {
-		use crate::wasm;
-		use js_sys::{ArrayBuffer, Uint8Array};
-		use wasm_bindgen::JsCast;
-		use wasm_bindgen_futures::JsFuture;
-		use web_sys::{Request, RequestInit, RequestMode, Response};
-
-		wasm::log(&format!("Rust getting tile {}x{}x{}", x, y, z));
-		// Use 'fetch' from JS
-		let url = self.get_url(x, y, z);
-		wasm::log(&format!("URL: {}", url));
-
-		let mut opts = RequestInit::new();
-		opts.method("GET");
-		opts.mode(RequestMode::Cors);
-		let request = Request::new_with_str_and_init(&url, &opts).unwrap();
-		let window = web_sys::window().unwrap();
-		let resp_value = JsFuture::from(window.fetch_with_request(&request)).await.unwrap();
-		let resp: Response = resp_value.dyn_into().unwrap();
-		let body: ArrayBuffer = JsFuture::from(resp.array_buffer().unwrap())
-			.await
-			.unwrap()
-			.dyn_into()
-			.unwrap();
-		let bytes = Uint8Array::new(&body).to_vec();
+/// Something that can provide tiles for a `Globe`
+///
+/// Futures aren't required to be `Send` because the wasm sources are built on `JsFuture`s.
+#[async_trait(?Send)]
+pub trait TileSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile>;
 
-		// Decode PBF
-		let mut reader = Reader::from_bytes(bytes);
-		let vt = reader.read(|r, b| VectorTile::from_reader(r, b)).unwrap();
-		Tile::from_vector_tile(vt, x, y, z)
+	/// Deepest zoom level the source has tiles for, if it has a limit
+	///
+	/// The `Globe` builds tiles past this from their ancestor at this level.
+	fn max_zoom(&self) -> Option<i32> {
+		None
 	}
+}
 
-	#[cfg(not(target_arch = "wasm32"))]
-	pub async fn get_tile(&self, x: i32, y: i32, z: i32) -> Tile {
-		// Read from web
-		let mut res = ureq::get(&self.get_url(x, y, z)).call().into_reader();
-		let mut gz_pbf = vec![];
-		res.read_to_end(&mut gz_pbf).unwrap();
-
-		// Decode gzip
-		let mut pbf = GzDecoder::new(&*gz_pbf);
-		let mut bytes = vec![];
-		pbf.read_to_end(&mut bytes).unwrap();
+/// A tile's raw, possibly compressed, payload
+#[derive(Clone, Debug, Default)]
+pub struct TileData {
+	pub bytes: Vec<u8>,
+	/// Validator to send back when revalidating the tile
+	pub etag: Option<String>,
+	/// Seconds the payload can be used before revalidating, if the source said
+	pub max_age: Option<u64>,
+	/// The caller's copy matching the requested ETag is still current, so `bytes` is empty
+	pub not_modified: bool,
+	/// The source forbids keeping a copy, e.g. `Cache-Control: no-store`
+	pub no_store: bool,
+}
 
-		// Decode PBF
-		let mut reader = Reader::from_bytes(bytes);
-		let vt = reader.read(|r, b| VectorTile::from_reader(r, b)).unwrap();
-		println!("Hello, world! {:?}", vt);
-		Tile::from_vector_tile(vt, x, y, z)
-	}
+/// A source that can hand out tile payloads before they're decoded, so they can be cached or inspected
+#[async_trait(?Send)]
+pub trait TileDataSource {
+	/// Fetch the payload for a tile. Missing tiles have empty `bytes`.
+	///
+	/// When `etag` is given a source may answer with `not_modified` instead of sending the payload again.
+	async fn get_tile_data(&self, x: i32, y: i32, z: i32, etag: Option<&str>) -> Result<TileData>;
 }
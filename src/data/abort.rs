@@ -0,0 +1,24 @@
+use crate::error::{Error, Result};
+use web_sys::{AbortController, RequestInit};
+
+/// Aborts a fetch when dropped
+///
+/// Futures are cancelled by dropping them, but the browser carries on with
+/// the request regardless. Holding one of these for the life of the fetch
+/// means the request is abandoned too. Aborting a finished fetch does nothing.
+pub(crate) struct AbortGuard(AbortController);
+
+impl AbortGuard {
+	/// Hook a new guard up to the request's options
+	pub fn new(opts: &mut RequestInit) -> Result<Self> {
+		let controller = AbortController::new().map_err(|e| Error::Network(format!("{:?}", e)))?;
+		opts.signal(Some(&controller.signal()));
+		Ok(Self(controller))
+	}
+}
+
+impl Drop for AbortGuard {
+	fn drop(&mut self) {
+		self.0.abort();
+	}
+}
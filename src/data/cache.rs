@@ -0,0 +1,418 @@
+use crate::data::decode::decode_tile;
+use crate::data::{TileData, TileDataSource, TileSource};
+use crate::error::Result;
+use crate::log;
+use crate::tile::{Tile, VectorOptions};
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, path::Path, path::PathBuf};
+
+// Used when the source doesn't say how long its tiles are good for
+const DEFAULT_MAX_AGE: u64 = 60 * 60 * 24;
+
+// Unix time in seconds
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) fn now() -> u64 {
+	use std::time::{SystemTime, UNIX_EPOCH};
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(super) fn now() -> u64 {
+	(js_sys::Date::now() / 1000.0) as u64
+}
+
+/// A cached tile payload
+#[derive(Clone, Debug, Default)]
+pub struct CacheEntry {
+	pub bytes: Vec<u8>,
+	pub etag: Option<String>,
+	/// Unix time in seconds after which the entry has to be revalidated
+	pub expires: u64,
+}
+
+/// Somewhere to keep cached tile payloads
+pub trait CacheStore {
+	fn get(&self, x: i32, y: i32, z: i32) -> Option<CacheEntry>;
+	fn put(&self, x: i32, y: i32, z: i32, entry: &CacheEntry) -> Result<()>;
+}
+
+/// Keeps payloads for as long as the store is alive. Used on wasm where there's no disk to write to.
+#[derive(Debug, Default)]
+pub struct MemoryCacheStore {
+	entries: RefCell<HashMap<(i32, i32, i32), CacheEntry>>,
+}
+
+impl MemoryCacheStore {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl CacheStore for MemoryCacheStore {
+	fn get(&self, x: i32, y: i32, z: i32) -> Option<CacheEntry> {
+		self.entries.borrow().get(&(x, y, z)).cloned()
+	}
+
+	fn put(&self, x: i32, y: i32, z: i32, entry: &CacheEntry) -> Result<()> {
+		self.entries.borrow_mut().insert((x, y, z), entry.clone());
+		Ok(())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize, Deserialize)]
+struct DiskEntryInfo {
+	etag: Option<String>,
+	expires: u64,
+}
+
+/// Stores payloads in a `{z}/{x}/{y}.tile` tree, with the caching headers alongside in `{y}.json`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct DiskCacheStore {
+	root: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DiskCacheStore {
+	pub fn new<P: AsRef<Path>>(root: P) -> Self {
+		Self {
+			root: root.as_ref().into(),
+		}
+	}
+
+	fn tile_path(&self, x: i32, y: i32, z: i32, ext: &str) -> PathBuf {
+		self.root
+			.join(z.to_string())
+			.join(x.to_string())
+			.join(format!("{}.{}", y, ext))
+	}
+
+	// Write to a temporary file first so a crash never leaves a half written tile behind
+	fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+		let tmp = path.with_extension("tmp");
+		fs::write(&tmp, contents)?;
+		fs::rename(&tmp, path)?;
+		Ok(())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CacheStore for DiskCacheStore {
+	fn get(&self, x: i32, y: i32, z: i32) -> Option<CacheEntry> {
+		let info = fs::read(self.tile_path(x, y, z, "json")).ok()?;
+		let info: DiskEntryInfo = serde_json::from_slice(&info).ok()?;
+		let bytes = fs::read(self.tile_path(x, y, z, "tile")).ok()?;
+		Some(CacheEntry {
+			bytes,
+			etag: info.etag,
+			expires: info.expires,
+		})
+	}
+
+	fn put(&self, x: i32, y: i32, z: i32, entry: &CacheEntry) -> Result<()> {
+		let path = self.tile_path(x, y, z, "tile");
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		let info = DiskEntryInfo {
+			etag: entry.etag.clone(),
+			expires: entry.expires,
+		};
+		let info = serde_json::to_vec(&info).expect("Cache info is always serializable");
+
+		// The info file is written last, an entry without one is ignored
+		Self::write_atomic(&path, &entry.bytes)?;
+		Self::write_atomic(&self.tile_path(x, y, z, "json"), &info)?;
+		Ok(())
+	}
+}
+
+/// Wraps a tile source and keeps the raw payloads it returns, honouring `Cache-Control: max-age` and revalidating
+/// expired tiles with their `ETag`. Payloads the source marks `no-store` are passed through without being kept.
+pub struct TileCache<S> {
+	source: S,
+	store: Box<dyn CacheStore>,
+	default_max_age: u64,
+	options: VectorOptions,
+}
+
+impl<S: TileDataSource> TileCache<S> {
+	pub fn new(source: S, store: Box<dyn CacheStore>) -> Self {
+		Self {
+			source,
+			store,
+			default_max_age: DEFAULT_MAX_AGE,
+			options: VectorOptions::default(),
+		}
+	}
+
+	pub fn in_memory(source: S) -> Self {
+		Self::new(source, Box::new(MemoryCacheStore::new()))
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn on_disk<P: AsRef<Path>>(source: S, root: P) -> Self {
+		Self::new(source, Box::new(DiskCacheStore::new(root)))
+	}
+
+	/// How long to keep tiles when the source doesn't send a `max-age`
+	pub fn with_default_max_age(mut self, seconds: u64) -> Self {
+		self.default_max_age = seconds;
+		self
+	}
+
+	/// How vector tiles are turned into meshes, e.g. how far past the edge features are kept and how wide lines are
+	pub fn with_vector_options(mut self, options: VectorOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+
+	fn store(&self, x: i32, y: i32, z: i32, entry: &CacheEntry) {
+		if let Err(err) = self.store.put(x, y, z, entry) {
+			log(&format!("Failed to cache tile {}x{}x{}: {}", x, y, z, err));
+		}
+	}
+}
+
+#[async_trait(?Send)]
+impl<S: TileDataSource> TileDataSource for TileCache<S> {
+	async fn get_tile_data(&self, x: i32, y: i32, z: i32, etag: Option<&str>) -> Result<TileData> {
+		let cached = self.store.get(x, y, z);
+		let now = now();
+
+		if let Some(entry) = &cached {
+			if entry.expires > now {
+				let not_modified = etag.is_some() && etag == entry.etag.as_deref();
+				return Ok(TileData {
+					bytes: if not_modified { vec![] } else { entry.bytes.clone() },
+					etag: entry.etag.clone(),
+					max_age: Some(entry.expires - now),
+					not_modified,
+					no_store: false,
+				});
+			}
+		}
+
+		let validator = cached.as_ref().and_then(|e| e.etag.as_deref());
+		let data = match self.source.get_tile_data(x, y, z, validator).await {
+			Ok(data) => data,
+			Err(err) => match cached {
+				// Better to show an old tile than nothing
+				Some(entry) => {
+					log(&format!("Using stale tile {}x{}x{}: {}", x, y, z, err));
+					return Ok(TileData {
+						bytes: entry.bytes,
+						etag: entry.etag,
+						..TileData::default()
+					});
+				}
+				None => return Err(err),
+			},
+		};
+
+		let max_age = data.max_age.unwrap_or(self.default_max_age);
+		let entry = match (data.not_modified, cached) {
+			(true, Some(mut entry)) => {
+				entry.expires = now + max_age;
+				if data.etag.is_some() {
+					entry.etag = data.etag;
+				}
+				entry
+			}
+			_ => CacheEntry {
+				bytes: data.bytes,
+				etag: data.etag,
+				expires: now + max_age,
+			},
+		};
+		if !data.no_store {
+			self.store(x, y, z, &entry);
+		}
+
+		Ok(TileData {
+			bytes: entry.bytes,
+			etag: entry.etag,
+			max_age: Some(max_age),
+			not_modified: false,
+			no_store: data.no_store,
+		})
+	}
+}
+
+#[async_trait(?Send)]
+impl<S: TileDataSource + TileSource> TileSource for TileCache<S> {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		let data = self.get_tile_data(x, y, z, None).await?;
+		decode_tile(data.bytes, &self.options, x, y, z)
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		self.source.max_zoom()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::Error;
+	use futures::executor::block_on;
+	use std::collections::VecDeque;
+
+	// Hands out scripted responses in order and remembers the validator it was sent each time
+	#[derive(Default)]
+	struct StubSource {
+		responses: RefCell<VecDeque<Result<TileData>>>,
+		validators: RefCell<Vec<Option<String>>>,
+	}
+
+	impl StubSource {
+		fn new(responses: Vec<Result<TileData>>) -> Self {
+			Self {
+				responses: RefCell::new(responses.into()),
+				..Self::default()
+			}
+		}
+	}
+
+	#[async_trait(?Send)]
+	impl TileDataSource for StubSource {
+		async fn get_tile_data(&self, _x: i32, _y: i32, _z: i32, etag: Option<&str>) -> Result<TileData> {
+			self.validators.borrow_mut().push(etag.map(String::from));
+			self.responses.borrow_mut().pop_front().expect("Unexpected request")
+		}
+	}
+
+	fn tile(bytes: &[u8], etag: &str, max_age: u64) -> Result<TileData> {
+		Ok(TileData {
+			bytes: bytes.to_vec(),
+			etag: Some(etag.into()),
+			max_age: Some(max_age),
+			..TileData::default()
+		})
+	}
+
+	#[test]
+	fn serves_fresh_tiles_from_the_cache() {
+		let cache = TileCache::in_memory(StubSource::new(vec![tile(b"tile", "\"1\"", 60)]));
+		let first = block_on(cache.get_tile_data(1, 2, 3, None)).unwrap();
+		assert_eq!(first.bytes, b"tile");
+		assert_eq!(first.max_age, Some(60));
+
+		// The stub would panic if it were asked again
+		let second = block_on(cache.get_tile_data(1, 2, 3, None)).unwrap();
+		assert_eq!(second.bytes, b"tile");
+		assert_eq!(second.etag.as_deref(), Some("\"1\""));
+
+		// The caller already has this version
+		let third = block_on(cache.get_tile_data(1, 2, 3, Some("\"1\""))).unwrap();
+		assert!(third.not_modified);
+		assert!(third.bytes.is_empty());
+	}
+
+	#[test]
+	fn revalidates_expired_tiles() {
+		let not_modified = Ok(TileData {
+			not_modified: true,
+			max_age: Some(60),
+			..TileData::default()
+		});
+		let cache = TileCache::in_memory(StubSource::new(vec![tile(b"tile", "\"1\"", 0), not_modified]));
+		block_on(cache.get_tile_data(0, 0, 0, None)).unwrap();
+
+		// A max-age of 0 expires straight away, so this goes back to the source with the ETag
+		let data = block_on(cache.get_tile_data(0, 0, 0, None)).unwrap();
+		assert_eq!(data.bytes, b"tile");
+		assert_eq!(data.etag.as_deref(), Some("\"1\""));
+		assert!(!data.not_modified);
+		assert_eq!(*cache.source().validators.borrow(), vec![None, Some("\"1\"".to_string())]);
+
+		// And it's fresh again
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"tile");
+	}
+
+	#[test]
+	fn falls_back_to_stale_tiles() {
+		let cache = TileCache::in_memory(StubSource::new(vec![
+			tile(b"tile", "\"1\"", 0),
+			Err(Error::HttpStatus(503)),
+			Err(Error::HttpStatus(503)),
+		]));
+		block_on(cache.get_tile_data(0, 0, 0, None)).unwrap();
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"tile");
+
+		match block_on(cache.get_tile_data(1, 0, 1, None)) {
+			Err(Error::HttpStatus(503)) => {}
+			other => panic!("Expected HTTP 503, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn no_store_is_passed_through() {
+		let private = Ok(TileData {
+			bytes: b"private".to_vec(),
+			no_store: true,
+			..TileData::default()
+		});
+		let cache = TileCache::in_memory(StubSource::new(vec![private, tile(b"public", "\"2\"", 60)]));
+
+		let data = block_on(cache.get_tile_data(0, 0, 0, None)).unwrap();
+		assert_eq!(data.bytes, b"private");
+		assert!(data.no_store);
+		assert!(cache.store.get(0, 0, 0).is_none());
+
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"public");
+		assert_eq!(cache.source().validators.borrow().len(), 2);
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[test]
+	fn disk_store() {
+		let root = std::env::temp_dir().join(format!("papariki-cache-{}", std::process::id()));
+		let store = DiskCacheStore::new(&root);
+		assert!(store.get(1, 2, 3).is_none());
+
+		let entry = CacheEntry {
+			bytes: b"tile".to_vec(),
+			etag: Some("\"1\"".into()),
+			expires: 1234,
+		};
+		store.put(1, 2, 3, &entry).unwrap();
+		let read = store.get(1, 2, 3).unwrap();
+		assert_eq!((read.bytes, read.etag, read.expires), (entry.bytes, entry.etag, entry.expires));
+
+		// Overwriting replaces the tile and leaves no temporary files behind
+		store
+			.put(1, 2, 3, &CacheEntry {
+				bytes: b"newer".to_vec(),
+				..CacheEntry::default()
+			})
+			.unwrap();
+		assert_eq!(store.get(1, 2, 3).unwrap().bytes, b"newer");
+		let mut files: Vec<_> = fs::read_dir(root.join("3").join("1"))
+			.unwrap()
+			.map(|f| f.unwrap().file_name().into_string().unwrap())
+			.collect();
+		files.sort();
+		assert_eq!(files, vec!["2.json", "2.tile"]);
+
+		// A tile without its info file was never finished
+		fs::remove_file(store.tile_path(1, 2, 3, "json")).unwrap();
+		assert!(store.get(1, 2, 3).is_none());
+
+		fs::remove_dir_all(&root).unwrap();
+	}
+}
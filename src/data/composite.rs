@@ -0,0 +1,154 @@
+use crate::data::TileSource;
+use crate::error::Result;
+use crate::globe::fetch_tile;
+use crate::mercator::TileCoord;
+use crate::tile::Tile;
+use async_trait::async_trait;
+use futures::future::join_all;
+
+struct Child {
+	namespace: String,
+	source: Box<dyn TileSource>,
+}
+
+/// Merges the layers of several vector tile sources into one tile
+///
+/// Every source is asked for the same tile at once. Layer names are prefixed with their source's namespace, e.g.
+/// `overlay:roads`, unless the namespace is empty. A source that fails or has no tile is left out, the tile is only
+/// an error if every source failed. Sources that don't go as deep as the others have their deepest tiles cut up.
+///
+/// Only vector layers are merged, imagery and terrain are left out.
+#[derive(Default)]
+pub struct CompositeSource {
+	children: Vec<Child>,
+}
+
+impl CompositeSource {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a source whose layers are named `{namespace}:{layer}`
+	pub fn with_source(mut self, namespace: &str, source: impl TileSource + 'static) -> Self {
+		self.children.push(Child {
+			namespace: namespace.to_string(),
+			source: Box::new(source),
+		});
+		self
+	}
+
+	pub fn len(&self) -> usize {
+		self.children.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.children.is_empty()
+	}
+}
+
+#[async_trait(?Send)]
+impl TileSource for CompositeSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		let coord = TileCoord::new(x, y, z);
+		let results = join_all(self.children.iter().map(|child| fetch_tile(&*child.source, coord))).await;
+
+		let mut merged = Tile::new();
+		let mut error = None;
+		let mut succeeded = 0;
+		for (child, result) in self.children.iter().zip(results) {
+			match result {
+				Ok(tile) => {
+					merged.merge_layers(tile, &child.namespace);
+					succeeded += 1;
+				}
+				Err(e) => {
+					crate::log(&format!("Failed to load {} from '{}': {}", coord, child.namespace, e));
+					error = Some(e);
+				}
+			}
+		}
+
+		// Only give up when nothing came back, one broken source shouldn't blank the whole map
+		match error {
+			Some(e) if succeeded == 0 => Err(e),
+			_ => Ok(merged),
+		}
+	}
+
+	/// The deepest of the sources' limits, or `None` if any of them has no limit
+	fn max_zoom(&self) -> Option<i32> {
+		let mut deepest = None;
+		for child in &self.children {
+			deepest = deepest.max(Some(child.source.max_zoom()?));
+		}
+		deepest
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::GeoJsonSource;
+	use crate::error::Error;
+	use crate::geometry::LonLat;
+	use futures::executor::block_on;
+
+	struct FailingSource;
+
+	#[async_trait(?Send)]
+	impl TileSource for FailingSource {
+		async fn get_tile(&self, _x: i32, _y: i32, _z: i32) -> Result<Tile> {
+			Err(Error::HttpStatus(500))
+		}
+	}
+
+	fn point(max_zoom: i32) -> GeoJsonSource {
+		let point = serde_json::json!({"type": "Point", "coordinates": [10.0, 10.0]});
+		GeoJsonSource::with_options(&point, max_zoom, 3.0, 64.0).unwrap()
+	}
+
+	fn layer_names(tile: &Tile) -> Vec<&str> {
+		tile.layers().iter().map(|layer| layer.name()).collect()
+	}
+
+	#[test]
+	fn namespaces_layers() {
+		let source = CompositeSource::new().with_source("base", point(14)).with_source("", point(14));
+		let tile = block_on(source.get_tile(0, 0, 0)).unwrap();
+		assert_eq!(layer_names(&tile), vec!["base:geojsonLayer", "geojsonLayer"]);
+	}
+
+	#[test]
+	fn tolerates_failing_sources() {
+		let source = CompositeSource::new()
+			.with_source("broken", FailingSource)
+			.with_source("points", point(14));
+		let tile = block_on(source.get_tile(0, 0, 0)).unwrap();
+		assert_eq!(layer_names(&tile), vec!["points:geojsonLayer"]);
+
+		let source = CompositeSource::new().with_source("broken", FailingSource);
+		match block_on(source.get_tile(0, 0, 0)) {
+			Err(Error::HttpStatus(500)) => {}
+			other => panic!("Expected HTTP 500, got {:?}", other.map(|tile| tile.layers().len())),
+		}
+	}
+
+	#[test]
+	fn goes_as_deep_as_the_deepest_source() {
+		let source = CompositeSource::new()
+			.with_source("shallow", point(2))
+			.with_source("deep", point(10));
+		assert_eq!(source.max_zoom(), Some(10));
+
+		// The shallow source's zoom 2 tile is cut up to fill in
+		let coord = TileCoord::from_lonlat(&LonLat::new(10.0, 10.0), 6);
+		let tile = block_on(source.get_tile(coord.x, coord.y, coord.z)).unwrap();
+		assert_eq!(layer_names(&tile), vec!["shallow:geojsonLayer", "deep:geojsonLayer"]);
+		for layer in tile.layers() {
+			assert_eq!(layer.features().len(), 1);
+		}
+
+		assert_eq!(source.with_source("unlimited", FailingSource).max_zoom(), None);
+		assert_eq!(CompositeSource::new().max_zoom(), None);
+	}
+}
@@ -0,0 +1,274 @@
+use crate::error::{Error, Result};
+use crate::mesh::Texture;
+use crate::protos::vector_tile::Tile as VectorTile;
+use crate::tile::{Tile, VectorOptions};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use quick_protobuf::{BytesReader, MessageRead};
+use std::io::{self, Read};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const JPEG_SIGNATURE: [u8; 3] = [0xff, 0xd8, 0xff];
+
+// Whether a byte could start a raw vector tile: a one byte protobuf tag with a field number and a wire type that
+// isn't a deprecated group. Layers (field 3, length delimited) are 0x1a.
+fn is_pbf_tag(byte: u8) -> bool {
+	byte < 0x80 && byte >> 3 != 0 && matches!(byte & 0x07, 0 | 1 | 2 | 5)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+	/// Work it out from the payload
+	Unknown,
+	None,
+	Gzip,
+	Zlib,
+	Brotli,
+	Zstd,
+}
+
+/// Guess a tile payload's compression from its magic bytes
+///
+/// Brotli has no magic number, so it can't be detected. Payloads that aren't recognised and don't look like a raw
+/// vector tile come back as `Unknown`.
+pub fn detect_compression(bytes: &[u8]) -> Compression {
+	match bytes {
+		[] => Compression::None,
+		[0x1f, 0x8b, ..] => Compression::Gzip,
+		[0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+		[cmf, flg, ..] if cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31) => Compression::Zlib,
+		[tag, ..] if is_pbf_tag(*tag) => Compression::None,
+		_ => Compression::Unknown,
+	}
+}
+
+fn unsupported(compression: Compression) -> Error {
+	Error::Decompress(io::Error::new(
+		io::ErrorKind::InvalidData,
+		format!("Unsupported compression {:?}", compression),
+	))
+}
+
+fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>> {
+	let mut out = vec![];
+	reader.read_to_end(&mut out).map_err(Error::Decompress)?;
+	Ok(out)
+}
+
+fn unrecognised() -> Error {
+	Error::Decompress(io::Error::new(io::ErrorKind::InvalidData, "Unrecognised tile payload"))
+}
+
+/// Decompress a tile payload, sniffing the compression if it's `Unknown`
+///
+/// With the `brotli` feature, a payload that can't be sniffed is tried as brotli before giving up on it.
+pub fn decompress(bytes: Vec<u8>, compression: Compression) -> Result<Vec<u8>> {
+	let compression = match compression {
+		Compression::Unknown => detect_compression(&bytes),
+		c => c,
+	};
+
+	match compression {
+		#[cfg(feature = "brotli")]
+		Compression::Unknown => decompress(bytes, Compression::Brotli).map_err(|_| unrecognised()),
+		#[cfg(not(feature = "brotli"))]
+		Compression::Unknown => Err(unrecognised()),
+		Compression::None => Ok(bytes),
+		Compression::Gzip => read_all(GzDecoder::new(&*bytes)),
+		Compression::Zlib => read_all(ZlibDecoder::new(&*bytes)),
+		#[cfg(feature = "brotli")]
+		Compression::Brotli => read_all(brotli_decompressor::Decompressor::new(&*bytes, 4096)),
+		#[cfg(feature = "zstd")]
+		Compression::Zstd => {
+			let decoder = ruzstd::decoding::StreamingDecoder::new(&*bytes)
+				.map_err(|e| Error::Decompress(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?;
+			read_all(decoder)
+		}
+		#[allow(unreachable_patterns)]
+		other => Err(unsupported(other)),
+	}
+}
+
+/// Parse an uncompressed PBF without building any geometry, the tile borrows its strings from `bytes`
+pub fn read_vector_tile(bytes: &[u8]) -> Result<VectorTile<'_>> {
+	let mut reader = BytesReader::from_bytes(bytes);
+	Ok(VectorTile::from_reader(&mut reader, bytes)?)
+}
+
+/// Decode an uncompressed PBF into a tile
+pub fn decode_pbf(bytes: Vec<u8>, options: &VectorOptions, x: i32, y: i32, z: i32) -> Result<Tile> {
+	Tile::from_vector_tile_with_options(read_vector_tile(&bytes)?, options, x, y, z)
+}
+
+/// Decompress and decode a tile payload, whatever the source
+pub fn decode_tile(bytes: Vec<u8>, options: &VectorOptions, x: i32, y: i32, z: i32) -> Result<Tile> {
+	decode_pbf(decompress(bytes, Compression::Unknown)?, options, x, y, z)
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Texture> {
+	use png::{BitDepth, ColorType, Transformations};
+
+	let image_error = |e: png::DecodingError| Error::Image(e.to_string());
+	let mut decoder = png::Decoder::new(bytes);
+	// Palettes, low bit depths and tRNS chunks all come out as 8 bit gray, RGB or alpha
+	decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+	let mut reader = decoder.read_info().map_err(image_error)?;
+	let mut buf = vec![0; reader.output_buffer_size()];
+	let info = reader.next_frame(&mut buf).map_err(image_error)?;
+	buf.truncate(info.buffer_size());
+
+	let pixels = match reader.output_color_type() {
+		(ColorType::Rgba, BitDepth::Eight) => buf,
+		(ColorType::Rgb, BitDepth::Eight) => buf.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+		(ColorType::GrayscaleAlpha, BitDepth::Eight) => buf.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+		(ColorType::Grayscale, BitDepth::Eight) => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+		other => return Err(Error::Image(format!("Unsupported PNG format {:?}", other))),
+	};
+
+	Ok(Texture {
+		width: info.width,
+		height: info.height,
+		pixels,
+	})
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<Texture> {
+	use jpeg_decoder::PixelFormat;
+
+	let mut decoder = jpeg_decoder::Decoder::new(bytes);
+	let buf = decoder.decode().map_err(|e| Error::Image(e.to_string()))?;
+	let info = decoder
+		.info()
+		.ok_or_else(|| Error::Image("Missing JPEG header".into()))?;
+
+	let pixels = match info.pixel_format {
+		PixelFormat::RGB24 => buf.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+		PixelFormat::L8 => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+		// Big endian, keep the high byte
+		PixelFormat::L16 => buf.chunks(2).flat_map(|p| [p[0], p[0], p[0], 255]).collect(),
+		// Adobe JPEGs store CMYK inverted
+		PixelFormat::CMYK32 => buf
+			.chunks(4)
+			.flat_map(|p| {
+				let k = p[3] as u16;
+				let channel = |c: u8| (c as u16 * k / 255) as u8;
+				[channel(p[0]), channel(p[1]), channel(p[2]), 255]
+			})
+			.collect(),
+	};
+
+	Ok(Texture {
+		width: info.width as u32,
+		height: info.height as u32,
+		pixels,
+	})
+}
+
+/// Decode a PNG or JPEG raster tile into RGBA pixels
+pub fn decode_image(bytes: &[u8]) -> Result<Texture> {
+	if bytes.starts_with(&PNG_SIGNATURE) {
+		decode_png(bytes)
+	} else if bytes.starts_with(&JPEG_SIGNATURE) {
+		decode_jpeg(bytes)
+	} else {
+		Err(Error::Image("Not a PNG or JPEG".into()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PNG: &[u8] = include_bytes!("../../tests/fixtures/2x2.png");
+	// 16x8, the left half red and the right half blue
+	const JPEG: &[u8] = include_bytes!("../../tests/fixtures/red-blue.jpg");
+
+	fn pixel(texture: &Texture, x: u32, y: u32) -> [u8; 4] {
+		let i = ((y * texture.width + x) * 4) as usize;
+		[texture.pixels[i], texture.pixels[i + 1], texture.pixels[i + 2], texture.pixels[i + 3]]
+	}
+
+	fn assert_image_error(result: Result<Texture>) {
+		match result {
+			Err(Error::Image(_)) => {}
+			other => panic!("Expected an image error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn compression() {
+		use flate2::write::{GzEncoder, ZlibEncoder};
+		use std::io::Write;
+
+		// A layer called "a"
+		let pbf = vec![0x1a, 0x03, 0x0a, 0x01, b'a'];
+		let mut gzip = GzEncoder::new(vec![], flate2::Compression::default());
+		gzip.write_all(&pbf).unwrap();
+		let gzip = gzip.finish().unwrap();
+		let mut zlib = ZlibEncoder::new(vec![], flate2::Compression::default());
+		zlib.write_all(&pbf).unwrap();
+		let zlib = zlib.finish().unwrap();
+
+		assert_eq!(detect_compression(&gzip), Compression::Gzip);
+		assert_eq!(detect_compression(&zlib), Compression::Zlib);
+		assert_eq!(detect_compression(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Compression::Zstd);
+		assert_eq!(detect_compression(&pbf), Compression::None);
+		assert_eq!(detect_compression(&[]), Compression::None);
+		// Group wire types and field 0 can't start a tile
+		assert_eq!(detect_compression(&[0x1b, 0x00]), Compression::Unknown);
+		assert_eq!(detect_compression(&[0x02, 0x00]), Compression::Unknown);
+		assert_eq!(detect_compression(&[0xce, 0xb2, 0xcf]), Compression::Unknown);
+
+		assert_eq!(decompress(gzip, Compression::Unknown).unwrap(), pbf);
+		assert_eq!(decompress(zlib, Compression::Unknown).unwrap(), pbf);
+		assert_eq!(decompress(pbf.clone(), Compression::Unknown).unwrap(), pbf);
+		assert!(read_vector_tile(&pbf).is_ok());
+		match decompress(vec![0xce, 0xb2, 0xcf], Compression::Unknown) {
+			Err(Error::Decompress(_)) => {}
+			other => panic!("Expected a decompression error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn png() {
+		let texture = decode_image(PNG).unwrap();
+		assert_eq!((texture.width, texture.height), (2, 2));
+		assert_eq!(texture.pixels.len(), 2 * 2 * 4);
+		assert_eq!(pixel(&texture, 0, 0), [255, 0, 0, 255]);
+		assert_eq!(pixel(&texture, 1, 0), [0, 255, 0, 128]);
+		assert_eq!(pixel(&texture, 0, 1), [0, 0, 255, 255]);
+		assert_eq!(pixel(&texture, 1, 1), [255, 255, 255, 0]);
+	}
+
+	#[test]
+	fn jpeg() {
+		let texture = decode_image(JPEG).unwrap();
+		assert_eq!((texture.width, texture.height), (16, 8));
+		assert_eq!(texture.pixels.len(), 16 * 8 * 4);
+
+		// Lossy, and there's rounding going to YCbCr and back
+		let near = |a: [u8; 4], b: [u8; 4]| a.iter().zip(&b).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 3);
+		for y in 0..8 {
+			for x in 0..16 {
+				let expected = if x < 8 { [255, 0, 0, 255] } else { [0, 0, 255, 255] };
+				let actual = pixel(&texture, x, y);
+				assert!(near(actual, expected), "{:?} at {},{} should be {:?}", actual, x, y, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn bad_images() {
+		assert_image_error(decode_image(b""));
+		assert_image_error(decode_image(b"GIF89a"));
+		assert_image_error(decode_image(&PNG[..PNG.len() / 2]));
+		assert_image_error(decode_image(&JPEG[..40]));
+
+		// Right signature, garbage after it
+		let mut garbage = PNG_SIGNATURE.to_vec();
+		garbage.extend_from_slice(&[0x42; 64]);
+		assert_image_error(decode_image(&garbage));
+		let mut garbage = JPEG_SIGNATURE.to_vec();
+		garbage.extend_from_slice(&[0x42; 64]);
+		assert_image_error(decode_image(&garbage));
+	}
+}
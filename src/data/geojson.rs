@@ -0,0 +1,675 @@
+use crate::data::TileSource;
+use crate::error::{Error, Result};
+use crate::geometry::mvt::{self, ring_area, Properties};
+use crate::mercator::{TileCoord, MAX_ZOOM};
+use crate::tessellate::LineStyle;
+use crate::tile::{Layer, Tile};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+const DEFAULT_MAX_ZOOM: i32 = 14;
+// Tiles down to this zoom level are kept so deeper tiles can be cut from them
+const INDEX_MAX_ZOOM: i32 = 5;
+// Same as geojson-vt, in units of `EXTENT`
+const DEFAULT_TOLERANCE: f64 = 3.0;
+const DEFAULT_BUFFER: f64 = 64.0;
+const EXTENT: f64 = 4096.0;
+// Everything goes in one layer, named like the one geojson-vt tiles get
+const LAYER_NAME: &str = "geojsonLayer";
+
+// A projected point, 0.0 to 1.0 across the world, and how much it matters to the shape it's part of
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+	x: f64,
+	y: f64,
+	// Squared distance it sticks out from its simplified line, 1.0 for points that always stay
+	importance: f64,
+}
+
+impl Vertex {
+	fn new(x: f64, y: f64, importance: f64) -> Self {
+		Self { x, y, importance }
+	}
+
+	fn axis(&self, axis: usize) -> f64 {
+		if axis == 0 {
+			self.x
+		} else {
+			self.y
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+enum Geometry {
+	Points(Vec<Vertex>),
+	Lines(Vec<Vec<Vertex>>),
+	/// Rings of each polygon, exterior first, each closed
+	Polygons(Vec<Vec<Vec<Vertex>>>),
+}
+
+impl Geometry {
+	fn is_empty(&self) -> bool {
+		match self {
+			Geometry::Points(points) => points.is_empty(),
+			Geometry::Lines(parts) => parts.is_empty(),
+			Geometry::Polygons(polygons) => polygons.is_empty(),
+		}
+	}
+}
+
+// A projected geometry and the properties of the feature it came from, shared by every tile it's cut into
+#[derive(Clone, Debug)]
+struct Projected {
+	id: Option<u64>,
+	properties: Rc<Properties>,
+	geometry: Geometry,
+}
+
+type Features = Rc<Vec<Projected>>;
+
+// Web mercator, 0.0 to 1.0 across the world with the north-west corner at the origin
+fn project(coords: &Value) -> Result<Vertex> {
+	let lonlat = coords
+		.as_array()
+		.filter(|c| c.len() >= 2)
+		.and_then(|c| Some((c[0].as_f64()?, c[1].as_f64()?)))
+		.ok_or_else(|| Error::GeoJson(format!("Invalid position: {}", coords)))?;
+
+	let sin = lonlat.1.to_radians().sin();
+	let x = lonlat.0 / 360.0 + 0.5;
+	let y = 0.5 - 0.25 * ((1.0 + sin) / (1.0 - sin)).ln() / PI;
+	Ok(Vertex::new(x, y.clamp(0.0, 1.0), 0.0))
+}
+
+fn array<'a>(value: &'a Value, what: &str) -> Result<&'a Vec<Value>> {
+	value
+		.as_array()
+		.ok_or_else(|| Error::GeoJson(format!("Expected an array of {}", what)))
+}
+
+// Squared distance from a point to a segment
+fn sq_seg_dist(p: &Vertex, a: &Vertex, b: &Vertex) -> f64 {
+	let (mut x, mut y) = (a.x, a.y);
+	let (dx, dy) = (b.x - a.x, b.y - a.y);
+	if dx != 0.0 || dy != 0.0 {
+		let t = ((p.x - a.x) * dx + (p.y - a.y) * dy) / (dx * dx + dy * dy);
+		if t > 1.0 {
+			x = b.x;
+			y = b.y;
+		} else if t > 0.0 {
+			x += dx * t;
+			y += dy * t;
+		}
+	}
+	(p.x - x).powi(2) + (p.y - y).powi(2)
+}
+
+// Douglas-Peucker, but rather than dropping points mark how important they are so each zoom level can pick its own
+// tolerance later
+fn rank(points: &mut [Vertex], first: usize, last: usize, sq_tolerance: f64) {
+	let mut max_dist = sq_tolerance;
+	let mut index = None;
+	for i in first + 1..last {
+		let dist = sq_seg_dist(&points[i], &points[first], &points[last]);
+		if dist > max_dist {
+			index = Some(i);
+			max_dist = dist;
+		}
+	}
+
+	if let Some(index) = index {
+		points[index].importance = max_dist;
+		if index - first > 1 {
+			rank(points, first, index, sq_tolerance);
+		}
+		if last - index > 1 {
+			rank(points, index, last, sq_tolerance);
+		}
+	}
+}
+
+fn convert_line(coords: &Value, sq_tolerance: f64) -> Result<Vec<Vertex>> {
+	let mut line = array(coords, "positions")?
+		.iter()
+		.map(project)
+		.collect::<Result<Vec<_>>>()?;
+	if let Some(last) = line.len().checked_sub(1) {
+		line[0].importance = 1.0;
+		line[last].importance = 1.0;
+		rank(&mut line, 0, last, sq_tolerance);
+	}
+	Ok(line)
+}
+
+// A polygon's rings, leaving out the whole polygon if its exterior is too short to be a ring
+fn convert_polygon(coords: &Value, sq_tolerance: f64, polygons: &mut Vec<Vec<Vec<Vertex>>>) -> Result<()> {
+	let mut rings = vec![];
+	for (i, ring) in array(coords, "rings")?.iter().enumerate() {
+		let ring = convert_line(ring, sq_tolerance)?;
+		if ring.len() >= 4 {
+			rings.push(ring);
+		} else if i == 0 {
+			return Ok(());
+		}
+	}
+	if !rings.is_empty() {
+		polygons.push(rings);
+	}
+	Ok(())
+}
+
+// GeoJSON properties as tile properties, nested objects and arrays are kept as JSON text like geojson-vt does
+fn convert_properties(properties: &Value) -> Properties {
+	let object = match properties.as_object() {
+		Some(object) => object,
+		None => return Properties::new(),
+	};
+	object
+		.iter()
+		.filter_map(|(key, value)| {
+			let value = match value {
+				Value::Null => return None,
+				Value::Bool(b) => mvt::Value::Bool(*b),
+				Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+					(Some(n), _) => mvt::Value::Uint(n),
+					(None, Some(n)) => mvt::Value::Sint(n),
+					_ => mvt::Value::Double(n.as_f64()?),
+				},
+				Value::String(s) => mvt::Value::String(s.clone()),
+				other => mvt::Value::String(other.to_string()),
+			};
+			Some((key.clone(), value))
+		})
+		.collect()
+}
+
+// Project a GeoJSON Feature, or a bare geometry, adding a part to `out` for each geometry in it
+fn convert_feature(feature: &Value, sq_tolerance: f64, out: &mut Vec<Projected>) -> Result<()> {
+	let (geometry, id, properties) = if feature.get("type").and_then(Value::as_str) == Some("Feature") {
+		let id = feature.get("id").and_then(Value::as_u64);
+		(&feature["geometry"], id, convert_properties(&feature["properties"]))
+	} else {
+		(feature, None, Properties::new())
+	};
+	if geometry.is_null() {
+		return Ok(());
+	}
+
+	let mut geometries = vec![];
+	convert_geometry(geometry, sq_tolerance, &mut geometries)?;
+	let properties = Rc::new(properties);
+	out.extend(geometries.into_iter().map(|geometry| Projected {
+		id,
+		properties: properties.clone(),
+		geometry,
+	}));
+	Ok(())
+}
+
+// Project a GeoJSON geometry, adding it to `out`
+fn convert_geometry(geometry: &Value, sq_tolerance: f64, out: &mut Vec<Geometry>) -> Result<()> {
+	let kind = geometry.get("type").and_then(Value::as_str).unwrap_or_default();
+	if kind == "GeometryCollection" {
+		for geometry in array(&geometry["geometries"], "geometries")? {
+			convert_geometry(geometry, sq_tolerance, out)?;
+		}
+		return Ok(());
+	}
+
+	let coords = &geometry["coordinates"];
+	let converted = match kind {
+		"Point" => Geometry::Points(vec![project(coords)?]),
+		"MultiPoint" => Geometry::Points(array(coords, "positions")?.iter().map(project).collect::<Result<_>>()?),
+		"LineString" => Geometry::Lines(vec![convert_line(coords, sq_tolerance)?]),
+		"MultiLineString" => Geometry::Lines(
+			array(coords, "lines")?
+				.iter()
+				.map(|line| convert_line(line, sq_tolerance))
+				.collect::<Result<_>>()?,
+		),
+		"Polygon" => {
+			let mut polygons = vec![];
+			convert_polygon(coords, sq_tolerance, &mut polygons)?;
+			Geometry::Polygons(polygons)
+		}
+		"MultiPolygon" => {
+			let mut polygons = vec![];
+			for polygon in array(coords, "polygons")? {
+				convert_polygon(polygon, sq_tolerance, &mut polygons)?;
+			}
+			Geometry::Polygons(polygons)
+		}
+		_ => return Err(Error::GeoJson(format!("Unknown geometry type {:?}", kind))),
+	};
+	out.push(converted);
+	Ok(())
+}
+
+// Where a segment crosses `k` along an axis, which always stays when simplifying
+fn intersect(a: &Vertex, b: &Vertex, k: f64, axis: usize) -> Vertex {
+	let t = (k - a.axis(axis)) / (b.axis(axis) - a.axis(axis));
+	if axis == 0 {
+		Vertex::new(k, a.y + (b.y - a.y) * t, 1.0)
+	} else {
+		Vertex::new(a.x + (b.x - a.x) * t, k, 1.0)
+	}
+}
+
+// Cut a line down to the part between k1 and k2 along an axis, which may split it in several
+fn clip_line(line: &[Vertex], k1: f64, k2: f64, axis: usize, out: &mut Vec<Vec<Vertex>>) {
+	let mut slice = vec![];
+	let mut end_slice = |slice: &mut Vec<Vertex>| {
+		if slice.len() >= 2 {
+			out.push(std::mem::take(slice));
+		}
+		slice.clear();
+	};
+
+	for pair in line.windows(2) {
+		let (a, b) = (&pair[0], &pair[1]);
+		let (ak, bk) = (a.axis(axis), b.axis(axis));
+
+		if ak < k1 {
+			if bk >= k1 {
+				slice.push(intersect(a, b, k1, axis));
+			}
+			if bk > k2 {
+				slice.push(intersect(a, b, k2, axis));
+				end_slice(&mut slice);
+			}
+		} else if ak > k2 {
+			if bk <= k2 {
+				slice.push(intersect(a, b, k2, axis));
+			}
+			if bk < k1 {
+				slice.push(intersect(a, b, k1, axis));
+				end_slice(&mut slice);
+			}
+		} else {
+			slice.push(*a);
+			if bk < k1 {
+				slice.push(intersect(a, b, k1, axis));
+				end_slice(&mut slice);
+			} else if bk > k2 {
+				slice.push(intersect(a, b, k2, axis));
+				end_slice(&mut slice);
+			}
+		}
+	}
+
+	if let Some(last) = line.last() {
+		if last.axis(axis) >= k1 && last.axis(axis) <= k2 {
+			slice.push(*last);
+		}
+	}
+	end_slice(&mut slice);
+}
+
+// Sutherland–Hodgman against the two sides at k1 and k2 along an axis
+fn clip_ring(ring: &[Vertex], k1: f64, k2: f64, axis: usize) -> Vec<Vertex> {
+	let mut out = vec![];
+	for pair in ring.windows(2) {
+		let (a, b) = (&pair[0], &pair[1]);
+		let (ak, bk) = (a.axis(axis), b.axis(axis));
+
+		if ak >= k1 && ak <= k2 {
+			out.push(*a);
+		}
+		// Crossing into or out of the range, possibly both sides at once so keep them in order
+		let crosses_k1 = (ak < k1) != (bk < k1);
+		let crosses_k2 = (ak > k2) != (bk > k2);
+		let sides = if ak <= bk { [(k1, crosses_k1), (k2, crosses_k2)] } else { [(k2, crosses_k2), (k1, crosses_k1)] };
+		for &(k, crosses) in &sides {
+			if crosses {
+				out.push(intersect(a, b, k, axis));
+			}
+		}
+	}
+
+	out.dedup_by(|a, b| a.x == b.x && a.y == b.y);
+	if let (Some(first), Some(last)) = (out.first().copied(), out.last()) {
+		if first.x != last.x || first.y != last.y {
+			out.push(first);
+		}
+	}
+	if out.len() < 4 {
+		out.clear();
+	}
+	out
+}
+
+fn clip(features: &[Projected], k1: f64, k2: f64, axis: usize) -> Vec<Projected> {
+	features
+		.iter()
+		.map(|feature| {
+			let geometry = match &feature.geometry {
+				Geometry::Points(points) => Geometry::Points(
+					points
+						.iter()
+						.filter(|p| p.axis(axis) >= k1 && p.axis(axis) <= k2)
+						.copied()
+						.collect(),
+				),
+				Geometry::Lines(lines) => {
+					let mut out = vec![];
+					for line in lines {
+						clip_line(line, k1, k2, axis, &mut out);
+					}
+					Geometry::Lines(out)
+				}
+				// A polygon goes when its exterior does, its holes go one by one
+				Geometry::Polygons(polygons) => Geometry::Polygons(
+					polygons
+						.iter()
+						.filter_map(|rings| {
+							let exterior = clip_ring(&rings[0], k1, k2, axis);
+							if exterior.is_empty() {
+								return None;
+							}
+							let holes = rings[1..].iter().map(|ring| clip_ring(ring, k1, k2, axis));
+							Some(std::iter::once(exterior).chain(holes.filter(|ring| !ring.is_empty())).collect())
+						})
+						.collect(),
+				),
+			};
+			Projected {
+				id: feature.id,
+				properties: feature.properties.clone(),
+				geometry,
+			}
+		})
+		.filter(|feature| !feature.geometry.is_empty())
+		.collect()
+}
+
+/// Serves tiles cut from a GeoJSON document, in the style of geojson-vt
+///
+/// The document is projected and ranked for simplification once. Tiles are
+/// clipped out of their nearest indexed ancestor on demand, with a buffer
+/// around each so lines don't stop short at the edges, and simplified to suit
+/// their zoom level.
+///
+/// Everything goes in a single layer called `geojsonLayer`.
+pub struct GeoJsonSource {
+	index: RefCell<HashMap<TileCoord, Features>>,
+	max_zoom: i32,
+	tolerance: f64,
+	buffer: f64,
+	line_style: LineStyle,
+}
+
+impl GeoJsonSource {
+	/// Load a FeatureCollection, a single Feature, or a bare geometry
+	pub fn new(geojson: &Value) -> Result<Self> {
+		Self::with_options(geojson, DEFAULT_MAX_ZOOM, DEFAULT_TOLERANCE, DEFAULT_BUFFER)
+	}
+
+	/// `tolerance` is how far (in 1/4096ths of a tile) simplified lines can stray from the original, and `buffer` how
+	/// far past its edges each tile goes
+	pub fn with_options(geojson: &Value, max_zoom: i32, tolerance: f64, buffer: f64) -> Result<Self> {
+		if !(0..=MAX_ZOOM).contains(&max_zoom) {
+			return Err(Error::GeoJson(format!("Max zoom {} isn't between 0 and {}", max_zoom, MAX_ZOOM)));
+		}
+
+		// Anything smaller than this doesn't show even at max zoom
+		let sq_tolerance = (tolerance / ((1u64 << max_zoom) as f64 * EXTENT)).powi(2);
+
+		let mut features = vec![];
+		if geojson.get("type").and_then(Value::as_str) == Some("FeatureCollection") {
+			for feature in array(&geojson["features"], "features")? {
+				convert_feature(feature, sq_tolerance, &mut features)?;
+			}
+		} else {
+			convert_feature(geojson, sq_tolerance, &mut features)?;
+		}
+
+		let mut index = HashMap::new();
+		index.insert(TileCoord::default(), Rc::new(features));
+
+		Ok(Self {
+			index: RefCell::new(index),
+			max_zoom,
+			tolerance,
+			buffer,
+			line_style: LineStyle::default(),
+		})
+	}
+
+	/// How lines are stroked
+	pub fn with_line_style(mut self, line_style: LineStyle) -> Self {
+		self.line_style = line_style;
+		self
+	}
+
+	pub fn parse(json: &str) -> Result<Self> {
+		let geojson = serde_json::from_str(json).map_err(|e| Error::GeoJson(e.to_string()))?;
+		Self::new(&geojson)
+	}
+
+	// Features within a tile and its buffer, unsimplified
+	fn clip_tile(&self, features: &[Projected], x: i32, y: i32, z: i32) -> Vec<Projected> {
+		let z2 = (1u64 << z) as f64;
+		let buffer = self.buffer / EXTENT;
+		let (x, y) = (x as f64, y as f64);
+
+		let features = clip(features, (x - buffer) / z2, (x + 1.0 + buffer) / z2, 0);
+		clip(&features, (y - buffer) / z2, (y + 1.0 + buffer) / z2, 1)
+	}
+
+	// Unsimplified features for a tile, cut from the nearest indexed ancestor
+	fn features(&self, x: i32, y: i32, z: i32) -> Features {
+		let mut index = self.index.borrow_mut();
+		let tile = TileCoord::new(x, y, z);
+		if let Some(features) = index.get(&tile) {
+			return features.clone();
+		}
+
+		// Walk up to the nearest indexed ancestor, the root is always there
+		let mut features = index[&TileCoord::default()].clone();
+		let mut from = 0;
+		for zoom in (0..z).rev() {
+			if let Some(found) = index.get(&tile.ancestor(zoom)) {
+				features = found.clone();
+				from = zoom;
+				break;
+			}
+		}
+
+		// Then back down, indexing the tiles on the way so their siblings can reuse them
+		for zoom in from + 1..=z.min(INDEX_MAX_ZOOM) {
+			let coord = tile.ancestor(zoom);
+			features = if features.is_empty() {
+				features
+			} else {
+				Rc::new(self.clip_tile(&features, coord.x, coord.y, coord.z))
+			};
+			index.insert(coord, features.clone());
+		}
+
+		if z > INDEX_MAX_ZOOM && !features.is_empty() {
+			features = Rc::new(self.clip_tile(&features, x, y, z));
+		}
+		features
+	}
+}
+
+#[async_trait(?Send)]
+impl TileSource for GeoJsonSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		if !TileCoord::new(x, y, z).is_valid() {
+			return Ok(Tile::new());
+		}
+
+		let z2 = (1u64 << z) as f64;
+		let sq_tolerance = (self.tolerance / (z2 * EXTENT)).powi(2);
+		let to_tile = |p: &Vertex| {
+			mvt::Point::new(
+				((p.x * z2 - x as f64) * EXTENT).round() as i32,
+				((p.y * z2 - y as f64) * EXTENT).round() as i32,
+			)
+		};
+		// Drop the points too small to see at this zoom, and any that land on the same spot once rounded
+		let simplify = |part: &[Vertex]| {
+			let mut points: Vec<_> = part.iter().filter(|p| p.importance > sq_tolerance).map(to_tile).collect();
+			points.dedup();
+			points
+		};
+		let ring = |part: &[Vertex], exterior: bool| {
+			let mut points = simplify(part);
+			// Vector tile rings close implicitly
+			if points.len() > 1 && points.first() == points.last() {
+				points.pop();
+			}
+			// Wound the way vector tiles expect, the GeoJSON spec's winding is only a recommendation
+			let area = ring_area(&points);
+			if area == 0 || points.len() < 3 {
+				return None;
+			}
+			if (area > 0) != exterior {
+				points.reverse();
+			}
+			Some(points)
+		};
+
+		let mut features = vec![];
+		for feature in self.features(x, y, z).iter() {
+			let geometry = match &feature.geometry {
+				Geometry::Points(points) => {
+					let mut points: Vec<_> = points.iter().map(to_tile).collect();
+					match points.len() {
+						0 => None,
+						1 => Some(mvt::Geometry::Point(points.remove(0))),
+						_ => Some(mvt::Geometry::MultiPoint(points)),
+					}
+				}
+				Geometry::Lines(parts) => {
+					let mut lines: Vec<_> = parts.iter().map(|part| simplify(part)).filter(|l| l.len() >= 2).collect();
+					match lines.len() {
+						0 => None,
+						1 => Some(mvt::Geometry::LineString(lines.remove(0))),
+						_ => Some(mvt::Geometry::MultiLineString(lines)),
+					}
+				}
+				Geometry::Polygons(parts) => {
+					let mut polygons: Vec<_> = parts
+						.iter()
+						.filter_map(|rings| {
+							Some(mvt::Polygon {
+								exterior: ring(&rings[0], true)?,
+								interiors: rings[1..].iter().filter_map(|r| ring(r, false)).collect(),
+							})
+						})
+						.collect();
+					match polygons.len() {
+						0 => None,
+						1 => Some(mvt::Geometry::Polygon(polygons.remove(0))),
+						_ => Some(mvt::Geometry::MultiPolygon(polygons)),
+					}
+				}
+			};
+
+			if let Some(geometry) = geometry {
+				features.push(mvt::Feature {
+					id: feature.id,
+					geometry,
+					properties: (*feature.properties).clone(),
+				});
+			}
+		}
+
+		let layer = Layer::new(LAYER_NAME, EXTENT as u32, features)
+			.with_buffer(self.buffer as u32)
+			.with_line_style(self.line_style);
+		Ok(Tile::from_layers(vec![layer], TileCoord::new(x, y, z)))
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		Some(self.max_zoom)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	#[test]
+	fn zoom_out_of_range() {
+		let point = serde_json::json!({"type": "Point", "coordinates": [0.0, 0.0]});
+		for &max_zoom in &[-1, 31, 64] {
+			match GeoJsonSource::with_options(&point, max_zoom, DEFAULT_TOLERANCE, DEFAULT_BUFFER) {
+				Err(Error::GeoJson(_)) => {}
+				other => panic!("Expected an error for max zoom {}, got {:?}", max_zoom, other.err()),
+			}
+		}
+
+		let source = GeoJsonSource::new(&point).unwrap();
+		for &(x, y, z) in &[(0, 0, -1), (0, 0, 31), (0, 0, 40), (2, 0, 1), (0, -1, 1)] {
+			let tile = block_on(source.get_tile(x, y, z)).unwrap();
+			assert!(tile.layers().is_empty(), "{}/{}/{} isn't empty", z, x, y);
+		}
+		assert_eq!(block_on(source.get_tile(0, 0, 0)).unwrap().layers().len(), 1);
+	}
+
+	fn geometry(source: &GeoJsonSource, x: i32, y: i32, z: i32) -> mvt::Geometry {
+		let tile = block_on(source.get_tile(x, y, z)).unwrap();
+		let features = tile.layer(LAYER_NAME).unwrap().features();
+		assert_eq!(features.len(), 1);
+		features[0].geometry.clone()
+	}
+
+	#[test]
+	fn clips_to_the_buffer() {
+		let line = serde_json::json!({"type": "LineString", "coordinates": [[-90.0, 0.0], [90.0, 0.0]]});
+		let source = GeoJsonSource::new(&line).unwrap();
+		// The equator is the bottom edge of the top row of tiles
+		let points = vec![mvt::Point::new(2048, 4096), mvt::Point::new(4096 + 64, 4096)];
+		assert_eq!(geometry(&source, 0, 0, 1), mvt::Geometry::LineString(points));
+
+		let source = GeoJsonSource::with_options(&line, DEFAULT_MAX_ZOOM, DEFAULT_TOLERANCE, 0.0).unwrap();
+		let points = vec![mvt::Point::new(0, 0), mvt::Point::new(2048, 0)];
+		assert_eq!(geometry(&source, 1, 1, 1), mvt::Geometry::LineString(points));
+	}
+
+	#[test]
+	fn simplifies_less_as_it_zooms_in() {
+		let zigzag = serde_json::json!({
+			"type": "LineString",
+			"coordinates": [[1.0, 1.0], [1.5, 1.1], [2.0, 1.0], [2.5, 1.1], [3.0, 1.0]]
+		});
+		let source = GeoJsonSource::new(&zigzag).unwrap();
+		let point_count = |x, y, z| match geometry(&source, x, y, z) {
+			mvt::Geometry::LineString(points) => points.len(),
+			other => panic!("Expected a line, got {:?}", other),
+		};
+		// The zigzag is a fraction of a unit high at zoom 0, and tens of units at zoom 6
+		assert_eq!(point_count(0, 0, 0), 2);
+		assert_eq!(point_count(32, 31, 6), 5);
+	}
+
+	#[test]
+	fn rewinds_rings() {
+		let exterior = vec![[-10.0, -10.0], [10.0, -10.0], [10.0, 10.0], [-10.0, 10.0], [-10.0, -10.0]];
+		let hole = vec![[-5.0, -5.0], [-5.0, 5.0], [5.0, 5.0], [5.0, -5.0], [-5.0, -5.0]];
+		let reversed = |ring: &Vec<[f64; 2]>| ring.iter().rev().copied().collect::<Vec<_>>();
+
+		for rings in &[vec![exterior.clone(), hole.clone()], vec![reversed(&exterior), reversed(&hole)]] {
+			let polygon = serde_json::json!({"type": "Polygon", "coordinates": rings});
+			match geometry(&GeoJsonSource::new(&polygon).unwrap(), 0, 0, 0) {
+				mvt::Geometry::Polygon(polygon) => {
+					assert_eq!(polygon.exterior.len(), 4);
+					assert!(ring_area(&polygon.exterior) > 0);
+					assert_eq!(polygon.interiors.len(), 1);
+					assert_eq!(polygon.interiors[0].len(), 4);
+					assert!(ring_area(&polygon.interiors[0]) < 0);
+				}
+				other => panic!("Expected a polygon, got {:?}", other),
+			}
+		}
+	}
+}
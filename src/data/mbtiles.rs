@@ -0,0 +1,289 @@
+use crate::data::decode::{decode_image, decode_tile};
+use crate::data::{TileData, TileDataSource, TileSource};
+use crate::error::{Error, Result};
+use crate::mercator::TileCoord;
+use crate::tile::{Tile, VectorOptions};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A layer listed in the `vector_layers` entry of the MBTiles metadata
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct VectorLayer {
+	pub id: String,
+	#[serde(default)]
+	pub description: Option<String>,
+	#[serde(default, rename = "minzoom")]
+	pub min_zoom: Option<i32>,
+	#[serde(default, rename = "maxzoom")]
+	pub max_zoom: Option<i32>,
+	#[serde(default)]
+	pub fields: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct MetadataJson {
+	#[serde(default)]
+	vector_layers: Vec<VectorLayer>,
+}
+
+/// The contents of an MBTiles `metadata` table
+#[derive(Clone, Debug, Default)]
+pub struct MbTilesMetadata {
+	pub name: Option<String>,
+	pub format: Option<String>,
+	/// West, south, east, north in degrees
+	pub bounds: Option<[f32; 4]>,
+	pub min_zoom: Option<i32>,
+	pub max_zoom: Option<i32>,
+	pub vector_layers: Vec<VectorLayer>,
+	/// Every row of the table, including the ones parsed above
+	pub values: HashMap<String, String>,
+}
+
+impl MbTilesMetadata {
+	fn from_values(values: HashMap<String, String>) -> Result<Self> {
+		let parse_zoom = |key: &str| -> Result<Option<i32>> {
+			match values.get(key) {
+				Some(zoom) => zoom
+					.trim()
+					.parse()
+					.map(Some)
+					.map_err(|_| Error::Metadata(format!("Invalid {}: {}", key, zoom))),
+				None => Ok(None),
+			}
+		};
+
+		let bounds = match values.get("bounds") {
+			Some(bounds) => {
+				let parts = bounds
+					.split(',')
+					.map(|v| v.trim().parse::<f32>())
+					.collect::<std::result::Result<Vec<_>, _>>()
+					.map_err(|_| Error::Metadata(format!("Invalid bounds: {}", bounds)))?;
+				if parts.len() != 4 {
+					return Err(Error::Metadata(format!("Invalid bounds: {}", bounds)));
+				}
+				Some([parts[0], parts[1], parts[2], parts[3]])
+			}
+			None => None,
+		};
+
+		let vector_layers = match values.get("json") {
+			Some(json) => {
+				serde_json::from_str::<MetadataJson>(json)
+					.map_err(|e| Error::Metadata(e.to_string()))?
+					.vector_layers
+			}
+			None => vec![],
+		};
+
+		Ok(Self {
+			name: values.get("name").cloned(),
+			format: values.get("format").cloned(),
+			bounds,
+			min_zoom: parse_zoom("minzoom")?,
+			max_zoom: parse_zoom("maxzoom")?,
+			vector_layers,
+			values,
+		})
+	}
+}
+
+/// Reads vector or raster tiles from an MBTiles (SQLite) file, going by the `format` in its metadata
+pub struct MbTilesSource {
+	connection: Connection,
+	metadata: MbTilesMetadata,
+	raster: bool,
+	options: VectorOptions,
+}
+
+impl MbTilesSource {
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+		Self::from_connection(Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?)
+	}
+
+	fn from_connection(connection: Connection) -> Result<Self> {
+		let mut values = HashMap::new();
+		{
+			let mut stmt = connection.prepare("SELECT name, value FROM metadata")?;
+			let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+			for row in rows {
+				let (name, value) = row?;
+				values.insert(name, value);
+			}
+		}
+		let metadata = MbTilesMetadata::from_values(values)?;
+		// The spec requires a format, but older vector tilesets often leave it out
+		let raster = match metadata.format.as_deref() {
+			None | Some("pbf") => false,
+			Some("png") | Some("jpg") | Some("jpeg") => true,
+			Some(other) => return Err(Error::Metadata(format!("Unsupported format: {}", other))),
+		};
+
+		Ok(Self {
+			connection,
+			metadata,
+			raster,
+			options: VectorOptions::default(),
+		})
+	}
+
+	/// How vector tiles are turned into meshes, e.g. how far past the edge features are kept and how wide lines are
+	pub fn with_vector_options(mut self, options: VectorOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	pub fn metadata(&self) -> &MbTilesMetadata {
+		&self.metadata
+	}
+
+	/// Raw `tile_data` for an XYZ tile, or `None` if the file doesn't contain it or the tile is off the map
+	pub fn read_tile_data(&self, x: i32, y: i32, z: i32) -> Result<Option<Vec<u8>>> {
+		let coord = TileCoord::new(x, y, z);
+		if !coord.is_valid() {
+			return Ok(None);
+		}
+		// MBTiles rows use the TMS scheme, which counts from the bottom
+		let row = coord.tiles_across() - 1 - y as i64;
+		let data = self
+			.connection
+			.query_row(
+				"SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+				params![z, x, row],
+				|row| row.get::<_, Vec<u8>>(0),
+			)
+			.optional()?;
+		Ok(data)
+	}
+}
+
+#[async_trait(?Send)]
+impl TileDataSource for MbTilesSource {
+	async fn get_tile_data(&self, x: i32, y: i32, z: i32, _etag: Option<&str>) -> Result<TileData> {
+		Ok(TileData {
+			bytes: self.read_tile_data(x, y, z)?.unwrap_or_default(),
+			..TileData::default()
+		})
+	}
+}
+
+#[async_trait(?Send)]
+impl TileSource for MbTilesSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		match self.read_tile_data(x, y, z)? {
+			Some(data) if self.raster => Ok(Tile::from_raster(decode_image(&data)?, x, y, z)),
+			Some(data) => decode_tile(data, &self.options, x, y, z),
+			// Missing tiles are usually empty ocean that was left out to save space
+			None => Ok(Tile::new()),
+		}
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		self.metadata.max_zoom
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	const PNG: &[u8] = include_bytes!("../../tests/fixtures/2x2.png");
+
+	fn tileset(metadata: &[(&str, &str)]) -> Connection {
+		let connection = Connection::open_in_memory().unwrap();
+		connection
+			.execute_batch(
+				"CREATE TABLE metadata (name TEXT, value TEXT);
+				CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);",
+			)
+			.unwrap();
+		for (name, value) in metadata {
+			connection
+				.execute(
+					"INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+					params![name, value],
+				)
+				.unwrap();
+		}
+		connection
+	}
+
+	fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+		pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+	}
+
+	#[test]
+	fn metadata_values() {
+		let metadata = MbTilesMetadata::from_values(values(&[
+			("name", "Roads"),
+			("format", "pbf"),
+			("bounds", "-180, -85.05, 180, 85.05"),
+			("minzoom", "0"),
+			("maxzoom", " 14 "),
+			(
+				"json",
+				r#"{"vector_layers": [{"id": "roads", "maxzoom": 12, "fields": {"class": "String"}}]}"#,
+			),
+		]))
+		.unwrap();
+
+		assert_eq!(metadata.name.as_deref(), Some("Roads"));
+		assert_eq!(metadata.format.as_deref(), Some("pbf"));
+		assert_eq!(metadata.bounds, Some([-180.0, -85.05, 180.0, 85.05]));
+		assert_eq!((metadata.min_zoom, metadata.max_zoom), (Some(0), Some(14)));
+		assert_eq!(metadata.vector_layers.len(), 1);
+		assert_eq!(metadata.vector_layers[0].id, "roads");
+		assert_eq!(metadata.vector_layers[0].max_zoom, Some(12));
+		assert_eq!(metadata.vector_layers[0].fields["class"], "String");
+		assert_eq!(metadata.values.len(), 6);
+
+		assert!(MbTilesMetadata::from_values(values(&[("maxzoom", "lots")])).is_err());
+		assert!(MbTilesMetadata::from_values(values(&[("bounds", "1,2,3")])).is_err());
+		assert!(MbTilesMetadata::from_values(values(&[("json", "{")])).is_err());
+	}
+
+	#[test]
+	fn rows_count_from_the_bottom() {
+		let connection = tileset(&[("format", "pbf")]);
+		// Zoom 2 has 4 rows, so XYZ row 0 is TMS row 3
+		connection
+			.execute("INSERT INTO tiles VALUES (2, 1, 3, x'0102')", [])
+			.unwrap();
+		let source = MbTilesSource::from_connection(connection).unwrap();
+
+		assert_eq!(source.read_tile_data(1, 0, 2).unwrap(), Some(vec![1, 2]));
+		assert_eq!(source.read_tile_data(1, 3, 2).unwrap(), None);
+		assert_eq!(block_on(source.get_tile_data(1, 0, 2, None)).unwrap().bytes, vec![1, 2]);
+	}
+
+	#[test]
+	fn off_the_map() {
+		let source = MbTilesSource::from_connection(tileset(&[])).unwrap();
+		assert_eq!(source.read_tile_data(0, 0, 31).unwrap(), None);
+		assert_eq!(source.read_tile_data(0, 4, 2).unwrap(), None);
+		assert_eq!(source.read_tile_data(-1, 0, 2).unwrap(), None);
+	}
+
+	#[test]
+	fn formats() {
+		assert!(!MbTilesSource::from_connection(tileset(&[])).unwrap().raster);
+		assert!(
+			MbTilesSource::from_connection(tileset(&[("format", "png")]))
+				.unwrap()
+				.raster
+		);
+		assert!(MbTilesSource::from_connection(tileset(&[("format", "webp")])).is_err());
+
+		let connection = tileset(&[("format", "png")]);
+		connection
+			.execute("INSERT INTO tiles VALUES (0, 0, 0, ?1)", params![PNG])
+			.unwrap();
+		let source = MbTilesSource::from_connection(connection).unwrap();
+		assert!(block_on(source.get_tile(0, 0, 0)).is_ok());
+	}
+}
@@ -0,0 +1,616 @@
+use crate::data::decode::{decode_pbf, decompress, Compression};
+use crate::data::{RangeReader, TileData, TileDataSource, TileSource};
+use crate::error::{Error, Result};
+use crate::mercator::TileCoord;
+use crate::tile::{Tile, VectorOptions};
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const HEADER_LEN: usize = 127;
+// The spec guarantees the header and root directory fit in the first 16KiB
+const INITIAL_FETCH_LEN: u64 = 16384;
+const MAX_DIRECTORY_DEPTH: usize = 4;
+// Leaf directories are up to a few hundred KiB each once decoded, so only keep the recently used ones
+const MAX_CACHED_LEAVES: usize = 64;
+
+type Directory = Rc<Vec<Entry>>;
+
+fn compression_from_u8(value: u8) -> Compression {
+	match value {
+		1 => Compression::None,
+		2 => Compression::Gzip,
+		3 => Compression::Brotli,
+		4 => Compression::Zstd,
+		_ => Compression::Unknown,
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileType {
+	Unknown,
+	Mvt,
+	Png,
+	Jpeg,
+	Webp,
+	Avif,
+}
+
+impl From<u8> for TileType {
+	fn from(value: u8) -> Self {
+		match value {
+			1 => TileType::Mvt,
+			2 => TileType::Png,
+			3 => TileType::Jpeg,
+			4 => TileType::Webp,
+			5 => TileType::Avif,
+			_ => TileType::Unknown,
+		}
+	}
+}
+
+/// The fixed size header at the start of a PMTiles v3 archive
+#[derive(Clone, Debug)]
+pub struct PmTilesHeader {
+	pub root_directory_offset: u64,
+	pub root_directory_length: u64,
+	pub metadata_offset: u64,
+	pub metadata_length: u64,
+	pub leaf_directories_offset: u64,
+	pub leaf_directories_length: u64,
+	pub tile_data_offset: u64,
+	pub tile_data_length: u64,
+	pub addressed_tiles_count: u64,
+	pub tile_entries_count: u64,
+	pub tile_contents_count: u64,
+	pub clustered: bool,
+	pub internal_compression: Compression,
+	pub tile_compression: Compression,
+	pub tile_type: TileType,
+	pub min_zoom: u8,
+	pub max_zoom: u8,
+	/// West, south, east, north in degrees
+	pub bounds: [f32; 4],
+	pub center_zoom: u8,
+	/// Longitude and latitude in degrees
+	pub center: [f32; 2],
+}
+
+impl PmTilesHeader {
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		if bytes.len() < HEADER_LEN || &bytes[0..7] != b"PMTiles" {
+			return Err(Error::Metadata("Not a PMTiles archive".into()));
+		}
+		if bytes[7] != 3 {
+			return Err(Error::Metadata(format!("Unsupported PMTiles version {}", bytes[7])));
+		}
+
+		let u64_at = |i: usize| {
+			let mut buf = [0; 8];
+			buf.copy_from_slice(&bytes[i..i + 8]);
+			u64::from_le_bytes(buf)
+		};
+		let degrees_at = |i: usize| {
+			let mut buf = [0; 4];
+			buf.copy_from_slice(&bytes[i..i + 4]);
+			i32::from_le_bytes(buf) as f32 / 10_000_000.0
+		};
+
+		Ok(Self {
+			root_directory_offset: u64_at(8),
+			root_directory_length: u64_at(16),
+			metadata_offset: u64_at(24),
+			metadata_length: u64_at(32),
+			leaf_directories_offset: u64_at(40),
+			leaf_directories_length: u64_at(48),
+			tile_data_offset: u64_at(56),
+			tile_data_length: u64_at(64),
+			addressed_tiles_count: u64_at(72),
+			tile_entries_count: u64_at(80),
+			tile_contents_count: u64_at(88),
+			clustered: bytes[96] == 1,
+			internal_compression: compression_from_u8(bytes[97]),
+			tile_compression: compression_from_u8(bytes[98]),
+			tile_type: bytes[99].into(),
+			min_zoom: bytes[100],
+			max_zoom: bytes[101],
+			bounds: [degrees_at(102), degrees_at(106), degrees_at(110), degrees_at(114)],
+			center_zoom: bytes[118],
+			center: [degrees_at(119), degrees_at(123)],
+		})
+	}
+}
+
+/// A directory entry. A `run_length` of 0 points at a leaf directory, otherwise it's the number of consecutive tile
+/// IDs sharing the same tile data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+	pub tile_id: u64,
+	pub offset: u64,
+	pub length: u32,
+	pub run_length: u32,
+}
+
+struct VarintReader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> VarintReader<'a> {
+	fn next(&mut self) -> Result<u64> {
+		let mut value = 0u64;
+		for shift in (0..64).step_by(7) {
+			let byte = *self
+				.bytes
+				.get(self.pos)
+				.ok_or_else(|| Error::Metadata("Truncated PMTiles directory".into()))?;
+			self.pos += 1;
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		Err(Error::Metadata("Varint too long in PMTiles directory".into()))
+	}
+}
+
+/// Decode an uncompressed directory
+pub fn decode_directory(bytes: &[u8]) -> Result<Vec<Entry>> {
+	let mut reader = VarintReader { bytes, pos: 0 };
+	let count = reader.next()? as usize;
+	if count > bytes.len() {
+		return Err(Error::Metadata("Invalid PMTiles directory length".into()));
+	}
+
+	let mut entries = vec![
+		Entry {
+			tile_id: 0,
+			offset: 0,
+			length: 0,
+			run_length: 0,
+		};
+		count
+	];
+
+	// Tile IDs are delta encoded
+	let mut last_id = 0;
+	for entry in entries.iter_mut() {
+		last_id += reader.next()?;
+		entry.tile_id = last_id;
+	}
+	for entry in entries.iter_mut() {
+		entry.run_length = reader.next()? as u32;
+	}
+	for entry in entries.iter_mut() {
+		entry.length = reader.next()? as u32;
+	}
+	// An offset of 0 means the data directly follows the previous entry, anything else is offset + 1
+	for i in 0..count {
+		let value = reader.next()?;
+		entries[i].offset = if value == 0 && i > 0 {
+			entries[i - 1].offset + entries[i - 1].length as u64
+		} else {
+			value.saturating_sub(1)
+		};
+	}
+
+	Ok(entries)
+}
+
+/// Find the entry containing `tile_id`, or the leaf directory that might
+pub fn find_entry(entries: &[Entry], tile_id: u64) -> Option<Entry> {
+	let idx = match entries.binary_search_by_key(&tile_id, |e| e.tile_id) {
+		Ok(idx) => return Some(entries[idx]),
+		Err(0) => return None,
+		Err(idx) => idx - 1,
+	};
+
+	let entry = entries[idx];
+	if entry.run_length == 0 || tile_id - entry.tile_id < entry.run_length as u64 {
+		Some(entry)
+	} else {
+		None
+	}
+}
+
+// Rotate/flip a quadrant of the Hilbert curve
+fn rotate(n: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+	if ry == 0 {
+		if rx == 1 {
+			*x = n - 1 - *x;
+			*y = n - 1 - *y;
+		}
+		std::mem::swap(x, y);
+	}
+}
+
+/// Convert a tile coordinate into its PMTiles tile ID: the number of tiles in all lower zoom levels plus the
+/// position along the Hilbert curve for this zoom level.
+pub fn zxy_to_tile_id(z: u8, x: u32, y: u32) -> u64 {
+	let n = 1u64 << z;
+	let base = ((1u64 << (2 * z as u64)) - 1) / 3;
+	let (mut x, mut y) = (x as u64, y as u64);
+	let mut d = 0;
+	let mut s = n / 2;
+	while s > 0 {
+		let rx = ((x & s) > 0) as u64;
+		let ry = ((y & s) > 0) as u64;
+		d += s * s * ((3 * rx) ^ ry);
+		rotate(n, &mut x, &mut y, rx, ry);
+		s /= 2;
+	}
+	base + d
+}
+
+/// The inverse of `zxy_to_tile_id`
+pub fn tile_id_to_zxy(tile_id: u64) -> (u8, u32, u32) {
+	let mut base = 0;
+	let mut z = 0;
+	while base + (1u64 << (2 * z)) <= tile_id {
+		base += 1u64 << (2 * z);
+		z += 1;
+	}
+
+	let n = 1u64 << z;
+	let mut t = tile_id - base;
+	let (mut x, mut y) = (0, 0);
+	let mut s = 1;
+	while s < n {
+		let rx = 1 & (t / 2);
+		let ry = 1 & (t ^ rx);
+		rotate(s, &mut x, &mut y, rx, ry);
+		x += s * rx;
+		y += s * ry;
+		t /= 4;
+		s *= 2;
+	}
+	(z as u8, x as u32, y as u32)
+}
+
+/// Reads vector tiles out of a single PMTiles v3 archive
+pub struct PmTilesSource {
+	reader: Box<dyn RangeReader>,
+	header: PmTilesHeader,
+	root: Vec<Entry>,
+	/// Recently used leaf directories keyed by offset and length, most recent last
+	leaves: RefCell<VecDeque<((u64, u64), Directory)>>,
+	options: VectorOptions,
+}
+
+impl PmTilesSource {
+	pub async fn open(reader: Box<dyn RangeReader>) -> Result<Self> {
+		let bytes = reader.read_range(0, INITIAL_FETCH_LEN).await?;
+		let header = PmTilesHeader::from_bytes(&bytes)?;
+
+		let start = header.root_directory_offset as usize;
+		let end = start + header.root_directory_length as usize;
+		let root_bytes = if end <= bytes.len() {
+			bytes[start..end].to_vec()
+		} else {
+			reader
+				.read_range(header.root_directory_offset, header.root_directory_length)
+				.await?
+		};
+		let root = decode_directory(&decompress(root_bytes, header.internal_compression)?)?;
+
+		Ok(Self {
+			reader,
+			header,
+			root,
+			leaves: RefCell::new(VecDeque::new()),
+			options: VectorOptions::default(),
+		})
+	}
+
+	/// How vector tiles are turned into meshes, e.g. how far past the edge features are kept and how wide lines are
+	pub fn with_vector_options(mut self, options: VectorOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	pub fn header(&self) -> &PmTilesHeader {
+		&self.header
+	}
+
+	/// The archive's JSON metadata
+	pub async fn metadata(&self) -> Result<serde_json::Value> {
+		let bytes = self
+			.reader
+			.read_range(self.header.metadata_offset, self.header.metadata_length)
+			.await?;
+		let json = decompress(bytes, self.header.internal_compression)?;
+		serde_json::from_slice(&json).map_err(|e| Error::Metadata(e.to_string()))
+	}
+
+	async fn leaf_directory(&self, offset: u64, length: u64) -> Result<Directory> {
+		let key = (offset, length);
+		{
+			let mut leaves = self.leaves.borrow_mut();
+			if let Some(idx) = leaves.iter().position(|(k, _)| *k == key) {
+				let leaf = leaves.remove(idx).unwrap();
+				let entries = leaf.1.clone();
+				leaves.push_back(leaf);
+				return Ok(entries);
+			}
+		}
+
+		let bytes = self
+			.reader
+			.read_range(self.header.leaf_directories_offset + offset, length)
+			.await?;
+		let entries = Rc::new(decode_directory(&decompress(bytes, self.header.internal_compression)?)?);
+		let mut leaves = self.leaves.borrow_mut();
+		if leaves.len() >= MAX_CACHED_LEAVES {
+			leaves.pop_front();
+		}
+		leaves.push_back((key, entries.clone()));
+		Ok(entries)
+	}
+
+	/// Raw (still compressed) tile data, or `None` if the archive doesn't contain the tile
+	pub async fn read_tile_data(&self, x: i32, y: i32, z: i32) -> Result<Option<Vec<u8>>> {
+		if !TileCoord::new(x, y, z).is_valid() || z < self.header.min_zoom as i32 || z > self.header.max_zoom as i32 {
+			return Ok(None);
+		}
+		let tile_id = zxy_to_tile_id(z as u8, x as u32, y as u32);
+
+		let mut entry = find_entry(&self.root, tile_id);
+		for _ in 0..MAX_DIRECTORY_DEPTH {
+			match entry {
+				Some(e) if e.run_length > 0 => {
+					let data = self
+						.reader
+						.read_range(self.header.tile_data_offset + e.offset, e.length as u64)
+						.await?;
+					return Ok(Some(data));
+				}
+				Some(e) => {
+					let leaf = self.leaf_directory(e.offset, e.length as u64).await?;
+					entry = find_entry(&leaf, tile_id);
+				}
+				None => return Ok(None),
+			}
+		}
+
+		Err(Error::Metadata("PMTiles directories nested too deeply".into()))
+	}
+}
+
+#[async_trait(?Send)]
+impl TileDataSource for PmTilesSource {
+	async fn get_tile_data(&self, x: i32, y: i32, z: i32, _etag: Option<&str>) -> Result<TileData> {
+		Ok(TileData {
+			bytes: self.read_tile_data(x, y, z).await?.unwrap_or_default(),
+			..TileData::default()
+		})
+	}
+}
+
+#[async_trait(?Send)]
+impl TileSource for PmTilesSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		if self.header.tile_type != TileType::Mvt {
+			return Err(Error::Metadata(format!(
+				"Unsupported PMTiles tile type {:?}",
+				self.header.tile_type
+			)));
+		}
+
+		match self.read_tile_data(x, y, z).await? {
+			Some(data) => decode_pbf(decompress(data, self.header.tile_compression)?, &self.options, x, y, z),
+			None => Ok(Tile::new()),
+		}
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		Some(self.header.max_zoom as i32)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use std::cell::Cell;
+
+	fn entry(tile_id: u64, offset: u64, length: u32, run_length: u32) -> Entry {
+		Entry {
+			tile_id,
+			offset,
+			length,
+			run_length,
+		}
+	}
+
+	fn varints(values: &[u64]) -> Vec<u8> {
+		let mut out = vec![];
+		for &value in values {
+			let mut value = value;
+			while value >= 0x80 {
+				out.push((value & 0x7f) as u8 | 0x80);
+				value >>= 7;
+			}
+			out.push(value as u8);
+		}
+		out
+	}
+
+	// Every offset written out in full, as offset + 1
+	fn encode_directory(entries: &[Entry]) -> Vec<u8> {
+		let mut values = vec![entries.len() as u64];
+		let mut last_id = 0;
+		for e in entries {
+			values.push(e.tile_id - last_id);
+			last_id = e.tile_id;
+		}
+		values.extend(entries.iter().map(|e| e.run_length as u64));
+		values.extend(entries.iter().map(|e| e.length as u64));
+		values.extend(entries.iter().map(|e| e.offset + 1));
+		varints(&values)
+	}
+
+	// Offsets of the root directory, leaf directories and tile data, all uncompressed vector tiles at zooms 0 to 2
+	fn encode_header(sections: [(u64, u64); 3]) -> Vec<u8> {
+		let mut bytes = b"PMTiles\x03".to_vec();
+		let [root, leaves, data] = sections;
+		for value in [root.0, root.1, 0, 0, leaves.0, leaves.1, data.0, data.1, 5, 4, 3] {
+			bytes.extend_from_slice(&value.to_le_bytes());
+		}
+		bytes.extend_from_slice(&[0, 1, 1, 1, 0, 2]);
+		for degrees in [-180.0, -85.0, 180.0, 85.0] {
+			bytes.extend_from_slice(&((degrees * 10_000_000.0) as i32).to_le_bytes());
+		}
+		bytes.push(1);
+		for degrees in [174.7762, -41.2865] {
+			bytes.extend_from_slice(&((degrees * 10_000_000.0) as i32).to_le_bytes());
+		}
+		bytes
+	}
+
+	struct MemoryReader {
+		bytes: Vec<u8>,
+		reads: Rc<Cell<usize>>,
+	}
+
+	#[async_trait(?Send)]
+	impl RangeReader for MemoryReader {
+		async fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+			self.reads.set(self.reads.get() + 1);
+			let start = (offset as usize).min(self.bytes.len());
+			let end = (start + length as usize).min(self.bytes.len());
+			Ok(self.bytes[start..end].to_vec())
+		}
+	}
+
+	// Tile 0 in the root, tiles 1 and 2 sharing data and tile 4 in a leaf, tile 3 missing
+	fn archive() -> Vec<u8> {
+		let data = b"zeroonefour".to_vec();
+		let leaf = encode_directory(&[entry(1, 4, 3, 2), entry(4, 7, 4, 1)]);
+		let root = encode_directory(&[entry(0, 0, 4, 1), entry(1, 0, leaf.len() as u32, 0)]);
+
+		let root_offset = HEADER_LEN as u64;
+		let leaf_offset = root_offset + root.len() as u64;
+		let data_offset = leaf_offset + leaf.len() as u64;
+		let mut bytes = encode_header([
+			(root_offset, root.len() as u64),
+			(leaf_offset, leaf.len() as u64),
+			(data_offset, data.len() as u64),
+		]);
+		bytes.extend(root);
+		bytes.extend(leaf);
+		bytes.extend(data);
+		bytes
+	}
+
+	#[test]
+	fn header() {
+		let bytes = encode_header([(127, 10), (137, 20), (157, 30)]);
+		assert_eq!(bytes.len(), HEADER_LEN);
+
+		let header = PmTilesHeader::from_bytes(&bytes).unwrap();
+		assert_eq!((header.root_directory_offset, header.root_directory_length), (127, 10));
+		assert_eq!(
+			(header.leaf_directories_offset, header.leaf_directories_length),
+			(137, 20)
+		);
+		assert_eq!((header.tile_data_offset, header.tile_data_length), (157, 30));
+		assert_eq!(header.addressed_tiles_count, 5);
+		assert!(!header.clustered);
+		assert_eq!(header.internal_compression, Compression::None);
+		assert_eq!(header.tile_compression, Compression::None);
+		assert_eq!(header.tile_type, TileType::Mvt);
+		assert_eq!((header.min_zoom, header.max_zoom), (0, 2));
+		assert_eq!(header.bounds, [-180.0, -85.0, 180.0, 85.0]);
+		assert_eq!(header.center_zoom, 1);
+		assert!((header.center[0] - 174.7762).abs() < 1e-4);
+		assert!((header.center[1] + 41.2865).abs() < 1e-4);
+
+		assert!(PmTilesHeader::from_bytes(&bytes[..100]).is_err());
+		let mut v2 = bytes.clone();
+		v2[7] = 2;
+		assert!(PmTilesHeader::from_bytes(&v2).is_err());
+		let mut other = bytes;
+		other[0] = b'X';
+		assert!(PmTilesHeader::from_bytes(&other).is_err());
+	}
+
+	#[test]
+	fn directory() {
+		let entries = vec![entry(0, 0, 10, 1), entry(5, 100, 20, 3), entry(1000, 2000, 30, 0)];
+		assert_eq!(decode_directory(&encode_directory(&entries)).unwrap(), entries);
+
+		// Offsets of 0 after the first entry continue straight on from the previous one
+		let bytes = varints(&[3, 0, 1, 1, 1, 1, 1, 10, 20, 30, 51, 0, 0]);
+		assert_eq!(
+			decode_directory(&bytes).unwrap(),
+			vec![entry(0, 50, 10, 1), entry(1, 60, 20, 1), entry(2, 80, 30, 1)]
+		);
+
+		assert!(decode_directory(&bytes[..bytes.len() - 1]).is_err());
+		assert!(decode_directory(&varints(&[1000])).is_err());
+	}
+
+	#[test]
+	fn finding_entries() {
+		let entries = vec![entry(5, 0, 10, 3), entry(10, 10, 10, 0), entry(20, 20, 10, 1)];
+		assert_eq!(find_entry(&entries, 4), None);
+		assert_eq!(find_entry(&entries, 5), Some(entries[0]));
+		assert_eq!(find_entry(&entries, 7), Some(entries[0]));
+		// Past the end of the run
+		assert_eq!(find_entry(&entries, 8), None);
+		// Anything after a leaf entry might be in the leaf
+		assert_eq!(find_entry(&entries, 10), Some(entries[1]));
+		assert_eq!(find_entry(&entries, 19), Some(entries[1]));
+		assert_eq!(find_entry(&entries, 20), Some(entries[2]));
+		assert_eq!(find_entry(&entries, 21), None);
+		assert_eq!(find_entry(&[], 0), None);
+	}
+
+	#[test]
+	fn tile_ids() {
+		// From the reference implementation's tests
+		assert_eq!(zxy_to_tile_id(0, 0, 0), 0);
+		assert_eq!(zxy_to_tile_id(1, 0, 0), 1);
+		assert_eq!(zxy_to_tile_id(1, 0, 1), 2);
+		assert_eq!(zxy_to_tile_id(1, 1, 1), 3);
+		assert_eq!(zxy_to_tile_id(1, 1, 0), 4);
+		assert_eq!(zxy_to_tile_id(2, 0, 0), 5);
+		assert_eq!(zxy_to_tile_id(20, 0, 0), 366_503_875_925);
+		assert_eq!(tile_id_to_zxy(19_078_479), (12, 3423, 1763));
+
+		for &(z, x, y) in &[
+			(0, 0, 0),
+			(3, 5, 2),
+			(12, 3423, 1763),
+			(20, 1 << 19, 12345),
+			(30, (1 << 30) - 1, 0),
+		] {
+			assert_eq!(tile_id_to_zxy(zxy_to_tile_id(z, x, y)), (z, x, y));
+		}
+	}
+
+	#[test]
+	fn reading_tiles() {
+		let reads = Rc::new(Cell::new(0));
+		let reader = MemoryReader {
+			bytes: archive(),
+			reads: reads.clone(),
+		};
+		let source = block_on(PmTilesSource::open(Box::new(reader))).unwrap();
+		let read = |x, y, z| block_on(source.read_tile_data(x, y, z)).unwrap();
+
+		assert_eq!(read(0, 0, 0), Some(b"zero".to_vec()));
+		assert_eq!(read(0, 0, 1), Some(b"one".to_vec()));
+		assert_eq!(read(0, 1, 1), Some(b"one".to_vec()));
+		assert_eq!(read(1, 1, 1), None);
+		assert_eq!(read(1, 0, 1), Some(b"four".to_vec()));
+
+		// The leaf is only fetched once
+		assert_eq!(reads.get(), 1 + 1 + 4);
+
+		assert_eq!(read(0, 2, 1), None);
+		assert_eq!(read(-1, 0, 1), None);
+		assert_eq!(read(0, 0, 31), None);
+	}
+}
@@ -0,0 +1,108 @@
+use crate::error::Result;
+use async_trait::async_trait;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{cell::RefCell, fs::File, io::Read, io::Seek, io::SeekFrom, path::Path};
+
+/// Reads byte ranges out of a single large file, either on disk or remote
+#[async_trait(?Send)]
+pub trait RangeReader {
+	/// Read up to `length` bytes starting at `offset`. Fewer bytes are returned if the range runs past the end.
+	async fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct FileRangeReader {
+	file: RefCell<File>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileRangeReader {
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+		Ok(Self {
+			file: RefCell::new(File::open(path)?),
+		})
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait(?Send)]
+impl RangeReader for FileRangeReader {
+	async fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+		let mut file = self.file.borrow_mut();
+		file.seek(SeekFrom::Start(offset))?;
+		let mut bytes = Vec::with_capacity(length as usize);
+		file.by_ref().take(length).read_to_end(&mut bytes)?;
+		Ok(bytes)
+	}
+}
+
+/// Reads byte ranges from a URL using HTTP `Range` requests
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+pub struct HttpRangeReader {
+	url: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpRangeReader {
+	pub fn new(url: &str) -> Self {
+		Self { url: url.into() }
+	}
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+impl RangeReader for HttpRangeReader {
+	async fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+		use crate::error::Error;
+		use js_sys::{ArrayBuffer, Uint8Array};
+		use wasm_bindgen::JsCast;
+		use super::abort::AbortGuard;
+		use wasm_bindgen_futures::JsFuture;
+		use web_sys::{Headers, Request, RequestInit, RequestMode, Response};
+
+		let js_error = |e: wasm_bindgen::JsValue| Error::Network(format!("{:?}", e));
+
+		if length == 0 {
+			return Ok(vec![]);
+		}
+
+		let headers = Headers::new().map_err(js_error)?;
+		headers
+			.set("Range", &format!("bytes={}-{}", offset, offset + length - 1))
+			.map_err(js_error)?;
+
+		let mut opts = RequestInit::new();
+		opts.method("GET");
+		opts.mode(RequestMode::Cors);
+		opts.headers(&headers);
+		let _abort = AbortGuard::new(&mut opts)?;
+		let request = Request::new_with_str_and_init(&self.url, &opts).map_err(js_error)?;
+		let window = web_sys::window().unwrap();
+		let resp: Response = JsFuture::from(window.fetch_with_request(&request))
+			.await
+			.map_err(js_error)?
+			.dyn_into()
+			.map_err(js_error)?;
+		if !resp.ok() {
+			return Err(Error::HttpStatus(resp.status()));
+		}
+		let body: ArrayBuffer = JsFuture::from(resp.array_buffer().map_err(js_error)?)
+			.await
+			.map_err(js_error)?
+			.dyn_into()
+			.map_err(js_error)?;
+		let mut bytes = Uint8Array::new(&body).to_vec();
+
+		// Servers that ignore `Range` send the whole file back
+		if resp.status() == 200 {
+			let start = (offset as usize).min(bytes.len());
+			let end = (offset + length).min(bytes.len() as u64) as usize;
+			bytes = bytes[start..end].to_vec();
+		}
+
+		Ok(bytes)
+	}
+}
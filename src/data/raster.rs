@@ -0,0 +1,38 @@
+use crate::data::decode::decode_image;
+use crate::data::{TileDataSource, TileSource};
+use crate::error::Result;
+use crate::tile::Tile;
+use async_trait::async_trait;
+
+/// Serves PNG or JPEG imagery, e.g. satellite photos or OSM raster tiles, from any payload source
+///
+/// Wrap a `WebTileSource` pointed at an imagery URL (or a `TileCache` of one). Each tile is drawn as a patch of the
+/// globe with the image stretched over it.
+pub struct RasterSource<S> {
+	source: S,
+}
+
+impl<S: TileDataSource> RasterSource<S> {
+	pub fn new(source: S) -> Self {
+		Self { source }
+	}
+
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+}
+
+#[async_trait(?Send)]
+impl<S: TileDataSource + TileSource> TileSource for RasterSource<S> {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		let data = self.source.get_tile_data(x, y, z, None).await?;
+		if data.bytes.is_empty() {
+			return Ok(Tile::new());
+		}
+		Ok(Tile::from_raster(decode_image(&data.bytes)?, x, y, z))
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		self.source.max_zoom()
+	}
+}
@@ -0,0 +1,87 @@
+use crate::data::decode::decode_image;
+use crate::data::{TileDataSource, TileSource};
+use crate::error::Result;
+use crate::globe::fetch_tile;
+use crate::mercator::TileCoord;
+use crate::terrain::{HeightMap, TerrainEncoding};
+use crate::tile::Tile;
+use async_trait::async_trait;
+
+/// Builds the surface of the globe from elevation tiles, e.g. Mapbox Terrain-RGB or Terrarium PNGs
+///
+/// Real elevations are tiny next to the size of the Earth, Everest is about a thousandth of its radius, so they
+/// can be scaled up with `with_exaggeration`. Vector tiles from `with_overlay` are draped over the surface.
+pub struct TerrainSource<S> {
+	source: S,
+	encoding: TerrainEncoding,
+	exaggeration: f32,
+	overlay: Option<Box<dyn TileSource>>,
+}
+
+impl<S: TileDataSource> TerrainSource<S> {
+	pub fn new(source: S, encoding: TerrainEncoding) -> Self {
+		Self {
+			source,
+			encoding,
+			exaggeration: 1.0,
+			overlay: None,
+		}
+	}
+
+	/// Multiply every elevation by this much
+	pub fn with_exaggeration(mut self, exaggeration: f32) -> Self {
+		self.exaggeration = exaggeration;
+		self
+	}
+
+	/// Drape another source's vector geometry over the terrain
+	pub fn with_overlay(mut self, overlay: impl TileSource + 'static) -> Self {
+		self.overlay = Some(Box::new(overlay));
+		self
+	}
+
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+}
+
+impl<S: TileDataSource + TileSource> TerrainSource<S> {
+	async fn terrain_tile(&self, coord: TileCoord) -> Result<Tile> {
+		let TileCoord { x, y, z } = coord;
+		let data = self.source.get_tile_data(x, y, z, None).await?;
+		if data.bytes.is_empty() {
+			return Ok(Tile::new());
+		}
+		let heights = HeightMap::decode(&decode_image(&data.bytes)?, self.encoding)?;
+		Ok(Tile::from_terrain(heights, self.exaggeration, x, y, z))
+	}
+}
+
+#[async_trait(?Send)]
+impl<S: TileDataSource + TileSource> TileSource for TerrainSource<S> {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		let coord = TileCoord::new(x, y, z);
+
+		// The overlay might go deeper than the terrain, so cut the terrain up here rather than leaving it to the globe
+		let terrain = match self.source.max_zoom() {
+			Some(max_zoom) if z > max_zoom => {
+				let parent = coord.ancestor(max_zoom);
+				self.terrain_tile(parent).await?.overzoom(parent, coord)
+			}
+			_ => self.terrain_tile(coord).await?,
+		};
+
+		match &self.overlay {
+			Some(overlay) => Ok(terrain.drape(&fetch_tile(overlay.as_ref(), coord).await?, coord)),
+			None => Ok(terrain),
+		}
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		let max_zoom = self.source.max_zoom()?;
+		match &self.overlay {
+			Some(overlay) => Some(max_zoom.max(overlay.max_zoom()?)),
+			None => Some(max_zoom),
+		}
+	}
+}
@@ -0,0 +1,656 @@
+use crate::data::decode::decode_tile;
+use crate::data::cache::now;
+use crate::data::{TileData, TileDataSource, TileSource};
+use crate::error::{Error, Result};
+use crate::mercator::TileCoord;
+use crate::tile::{Tile, VectorOptions};
+use async_trait::async_trait;
+use std::cell::Cell;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Read;
+
+const MAPBOX_STREETS_URL: &str = "https://api.mapbox.com/v4/mapbox.mapbox-streets-v8/{z}/{x}/{y}.vector.pbf";
+const MAPBOX_STREETS_MAX_ZOOM: i32 = 16;
+const DEFAULT_SUBDOMAINS: [&str; 3] = ["a", "b", "c"];
+const DEFAULT_MAX_RETRIES: u32 = 4;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+// Longest we'll wait between attempts, including when the server asks for longer
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Percent encode everything except RFC 3986 unreserved characters
+fn url_encode(value: &str) -> String {
+	let mut encoded = String::with_capacity(value.len());
+	for byte in value.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+			_ => encoded.push_str(&format!("%{:02X}", byte)),
+		}
+	}
+	encoded
+}
+
+// Whether `Cache-Control` has `no-store`, which forbids keeping the response at all
+fn parse_no_store(cache_control: &str) -> bool {
+	cache_control.split(',').any(|d| d.trim().eq_ignore_ascii_case("no-store"))
+}
+
+// Seconds from `Cache-Control: max-age`, with `no-cache` meaning revalidate every time
+fn parse_max_age(cache_control: &str) -> Option<u64> {
+	let mut max_age = None;
+	for directive in cache_control.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+		if directive == "no-cache" || directive == "no-store" {
+			return Some(0);
+		}
+		if let Some(value) = directive.strip_prefix("max-age=") {
+			max_age = value.trim_matches('"').parse().ok();
+		}
+	}
+	max_age
+}
+
+// Seconds to wait from `Retry-After`, which is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str, now: u64) -> Option<u64> {
+	let value = value.trim();
+	if let Ok(seconds) = value.parse() {
+		return Some(seconds);
+	}
+	parse_http_date(value).map(|date| date.saturating_sub(now))
+}
+
+// Unix time from an IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+fn parse_http_date(value: &str) -> Option<u64> {
+	const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+	let parts: Vec<&str> = value.split_whitespace().collect();
+	if parts.len() != 6 || parts[5] != "GMT" {
+		return None;
+	}
+	let day: u64 = parts[1].parse().ok()?;
+	let month = MONTHS.iter().position(|m| *m == parts[2])? as u64 + 1;
+	let year: u64 = parts[3].parse().ok()?;
+	let time: Vec<u64> = parts[4].split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+	if time.len() != 3 || year < 1970 {
+		return None;
+	}
+
+	// Days since the epoch, counting years from March so the leap day comes last
+	let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+	let era_days = y * 365 + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1;
+	let days = era_days - 719_468;
+
+	Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+#[cfg(target_arch = "wasm32")]
+fn random() -> f64 {
+	js_sys::Math::random()
+}
+
+// Between 0 and 1, good enough for jitter
+#[cfg(not(target_arch = "wasm32"))]
+fn random() -> f64 {
+	use std::collections::hash_map::RandomState;
+	use std::hash::{BuildHasher, Hasher};
+
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u64(now());
+	(hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn sleep(delay: Duration) {
+	let promise = js_sys::Promise::new(&mut |resolve, _| match web_sys::window() {
+		Some(window) => {
+			let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, delay.as_millis() as i32);
+		}
+		None => {
+			let _ = resolve.call0(&wasm_bindgen::JsValue::NULL);
+		}
+	});
+	let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+// Waits on a thread of its own so other tiles can make progress on the executor in the meantime
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(delay: Duration) {
+	use std::sync::{Arc, Mutex};
+	use std::task::{Poll, Waker};
+	use std::thread;
+	use std::time::Instant;
+
+	let deadline = Instant::now() + delay;
+	let waker: Arc<Mutex<Option<Waker>>> = Arc::default();
+	let mut timer = None;
+	futures::future::poll_fn(|cx| {
+		if Instant::now() >= deadline {
+			return Poll::Ready(());
+		}
+		*waker.lock().unwrap() = Some(cx.waker().clone());
+		timer.get_or_insert_with(|| {
+			let waker = waker.clone();
+			thread::spawn(move || {
+				thread::sleep(deadline.saturating_duration_since(Instant::now()));
+				if let Some(waker) = waker.lock().unwrap().take() {
+					waker.wake();
+				}
+			})
+		});
+		Poll::Pending
+	})
+	.await
+}
+
+/// Fetches vector tiles over HTTP from a URL template
+///
+/// The template can contain `{z}`, `{x}`, `{y}`, `{-y}` (TMS row), `{quadkey}` and `{s}` (subdomain) placeholders.
+/// `{s}` rotates through `a`, `b` and `c` unless other subdomains are given.
+///
+/// Missing tiles (204 or 404) come back empty. Rate limiting (429) and server errors (5xx) are retried with
+/// exponential backoff, honouring `Retry-After`. After a 401 or 403 every tile fails with the same error without a
+/// request being made.
+#[derive(Clone, Debug)]
+pub struct WebTileSource {
+	template: String,
+	subdomains: Vec<String>,
+	headers: Vec<(String, String)>,
+	query: Vec<(String, String)>,
+	max_zoom: Option<i32>,
+	max_retries: u32,
+	retry_delay: Duration,
+	options: VectorOptions,
+	/// The status of the first 401 or 403, every request after it fails the same way
+	auth_failed: Cell<Option<u16>>,
+}
+
+impl Default for WebTileSource {
+	fn default() -> Self {
+		Self {
+			template: String::new(),
+			subdomains: vec![],
+			headers: vec![],
+			query: vec![],
+			max_zoom: None,
+			max_retries: DEFAULT_MAX_RETRIES,
+			retry_delay: DEFAULT_RETRY_DELAY,
+			options: VectorOptions::default(),
+			auth_failed: Cell::new(None),
+		}
+	}
+}
+
+impl WebTileSource {
+	pub fn new(template: &str) -> Self {
+		Self {
+			template: template.into(),
+			..Self::default()
+		}
+	}
+
+	/// The Mapbox Streets v8 tileset
+	pub fn mapbox(token: &str) -> Self {
+		Self::new(MAPBOX_STREETS_URL)
+			.with_query("access_token", token)
+			.with_max_zoom(MAPBOX_STREETS_MAX_ZOOM)
+	}
+
+	/// Subdomains to rotate through when filling in `{s}`
+	pub fn with_subdomains(mut self, subdomains: &[&str]) -> Self {
+		self.subdomains = subdomains.iter().map(|s| s.to_string()).collect();
+		self
+	}
+
+	/// Add a header to every tile request
+	pub fn with_header(mut self, name: &str, value: &str) -> Self {
+		self.headers.push((name.into(), value.into()));
+		self
+	}
+
+	/// Add a query parameter to every tile URL
+	pub fn with_query(mut self, name: &str, value: &str) -> Self {
+		self.query.push((name.into(), value.into()));
+		self
+	}
+
+	/// Deepest zoom level the server has tiles for
+	pub fn with_max_zoom(mut self, max_zoom: i32) -> Self {
+		self.max_zoom = Some(max_zoom);
+		self
+	}
+
+	/// Retry rate limited and failed requests up to `max_retries` times, doubling the delay from `delay` each time
+	pub fn with_retries(mut self, max_retries: u32, delay: Duration) -> Self {
+		self.max_retries = max_retries;
+		self.retry_delay = delay;
+		self
+	}
+
+	/// How vector tiles are turned into meshes, e.g. how far past the edge features are kept and how wide lines are
+	pub fn with_vector_options(mut self, options: VectorOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	/// The URL for a tile, only meaningful for tiles on the map
+	pub fn get_url(&self, x: i32, y: i32, z: i32) -> String {
+		let coord = TileCoord::new(x, y, z);
+		let mut url = self
+			.template
+			.replace("{z}", &z.to_string())
+			.replace("{x}", &x.to_string())
+			.replace("{y}", &y.to_string())
+			.replace("{-y}", &(coord.tiles_across() - 1 - y as i64).to_string());
+
+		if url.contains("{quadkey}") && coord.is_valid() {
+			url = url.replace("{quadkey}", &coord.quadkey());
+		}
+
+		if url.contains("{s}") {
+			// Pick the subdomain from the coordinate so a tile always hits the same host and stays cacheable
+			let pick = |len: usize| (x as i64 + y as i64).rem_euclid(len as i64) as usize;
+			// Same default as Leaflet, which is where most templates with `{s}` come from
+			let subdomain = if self.subdomains.is_empty() {
+				DEFAULT_SUBDOMAINS[pick(DEFAULT_SUBDOMAINS.len())]
+			} else {
+				&self.subdomains[pick(self.subdomains.len())]
+			};
+			url = url.replace("{s}", subdomain);
+		}
+
+		for (i, (name, value)) in self.query.iter().enumerate() {
+			let sep = if i == 0 && !url.contains('?') { '?' } else { '&' };
+			url.push(sep);
+			url.push_str(&url_encode(name));
+			url.push('=');
+			url.push_str(&url_encode(value));
+		}
+
+		url
+	}
+}
+
+// What we need from a response, however it was fetched
+struct HttpResponse {
+	status: u16,
+	etag: Option<String>,
+	cache_control: Option<String>,
+	retry_after: Option<String>,
+	/// Only read for successful responses
+	bytes: Vec<u8>,
+}
+
+impl WebTileSource {
+	#[cfg(target_arch = "wasm32")]
+	async fn fetch(&self, url: &str, etag: Option<&str>) -> Result<HttpResponse> {
+		use super::abort::AbortGuard;
+		use js_sys::{ArrayBuffer, Uint8Array};
+		use wasm_bindgen::JsCast;
+		use wasm_bindgen_futures::JsFuture;
+		use web_sys::{Headers, Request, RequestInit, RequestMode, Response};
+
+		let js_error = |e: wasm_bindgen::JsValue| Error::Network(format!("{:?}", e));
+
+		// Use 'fetch' from JS
+		let headers = Headers::new().map_err(js_error)?;
+		for (name, value) in &self.headers {
+			headers.set(name, value).map_err(js_error)?;
+		}
+		if let Some(etag) = etag {
+			headers.set("If-None-Match", etag).map_err(js_error)?;
+		}
+
+		let mut opts = RequestInit::new();
+		opts.method("GET");
+		opts.mode(RequestMode::Cors);
+		opts.headers(&headers);
+		let _abort = AbortGuard::new(&mut opts)?;
+		let request = Request::new_with_str_and_init(url, &opts).map_err(js_error)?;
+		let window = web_sys::window().unwrap();
+		let resp_value = JsFuture::from(window.fetch_with_request(&request))
+			.await
+			.map_err(js_error)?;
+		let resp: Response = resp_value.dyn_into().map_err(js_error)?;
+
+		// ETag and Retry-After are only visible if the server lists them in Access-Control-Expose-Headers
+		let resp_headers = resp.headers();
+		let header = |name: &str| resp_headers.get(name).ok().flatten();
+		let mut res = HttpResponse {
+			status: resp.status(),
+			etag: header("ETag"),
+			cache_control: header("Cache-Control"),
+			retry_after: header("Retry-After"),
+			bytes: vec![],
+		};
+
+		if resp.ok() {
+			let body: ArrayBuffer = JsFuture::from(resp.array_buffer().map_err(js_error)?)
+				.await
+				.map_err(js_error)?
+				.dyn_into()
+				.map_err(js_error)?;
+			res.bytes = Uint8Array::new(&body).to_vec();
+		}
+
+		Ok(res)
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	async fn fetch(&self, url: &str, etag: Option<&str>) -> Result<HttpResponse> {
+		// Read from web
+		let mut req = ureq::get(url);
+		for (name, value) in &self.headers {
+			req.set(name, value);
+		}
+		if let Some(etag) = etag {
+			req.set("If-None-Match", etag);
+		}
+		let resp = req.call();
+		if let Some(err) = resp.synthetic_error() {
+			return Err(Error::Network(err.to_string()));
+		}
+
+		let header = |name: &str| resp.header(name).map(|v| v.to_string());
+		let mut res = HttpResponse {
+			status: resp.status(),
+			etag: header("ETag"),
+			cache_control: header("Cache-Control"),
+			retry_after: header("Retry-After"),
+			bytes: vec![],
+		};
+
+		if resp.ok() {
+			resp.into_reader()
+				.read_to_end(&mut res.bytes)
+				.map_err(|e| Error::Network(e.to_string()))?;
+		}
+
+		Ok(res)
+	}
+
+	// How long to wait before the next attempt, or `None` to give up
+	fn retry_delay(&self, attempt: u32, retry_after: Option<&str>) -> Option<Duration> {
+		if attempt >= self.max_retries {
+			return None;
+		}
+
+		// The server knows best, unless it wants us to wait ages
+		if let Some(delay) = retry_after.and_then(|v| parse_retry_after(v, now())) {
+			let delay = Duration::from_secs(delay);
+			return if delay <= MAX_RETRY_DELAY { Some(delay) } else { None };
+		}
+
+		// Exponential backoff with "equal jitter", so a burst of failed tiles don't all retry in lockstep
+		let backoff = self.retry_delay.saturating_mul(1 << attempt.min(16)).min(MAX_RETRY_DELAY);
+		Some(backoff / 2 + backoff.mul_f64(random() / 2.0))
+	}
+}
+
+#[async_trait(?Send)]
+impl TileDataSource for WebTileSource {
+	async fn get_tile_data(&self, x: i32, y: i32, z: i32, etag: Option<&str>) -> Result<TileData> {
+		// The token's bad, so don't keep hammering the server. Still an error so a cache can use what it has.
+		if let Some(status) = self.auth_failed.get() {
+			return Err(Error::Unauthorized(status));
+		}
+
+		// There's nothing to ask for off the edge of the map
+		if !TileCoord::new(x, y, z).is_valid() {
+			return Ok(TileData::default());
+		}
+
+		let url = self.get_url(x, y, z);
+		let mut attempt = 0;
+		loop {
+			let res = self.fetch(&url, etag).await?;
+			let data = TileData {
+				etag: res.etag,
+				max_age: res.cache_control.as_deref().and_then(parse_max_age),
+				no_store: res.cache_control.as_deref().is_some_and(parse_no_store),
+				..TileData::default()
+			};
+
+			match res.status {
+				// No tile here, e.g. open ocean
+				204 | 404 => return Ok(data),
+				304 => {
+					return Ok(TileData {
+						not_modified: true,
+						..data
+					})
+				}
+				200..=299 => return Ok(TileData { bytes: res.bytes, ..data }),
+				401 | 403 => {
+					self.auth_failed.set(Some(res.status));
+					return Err(Error::Unauthorized(res.status));
+				}
+				429 | 500..=599 => match self.retry_delay(attempt, res.retry_after.as_deref()) {
+					Some(delay) => {
+						crate::log(&format!(
+							"Tile {}x{}x{} failed with HTTP {}, retrying in {}ms",
+							x,
+							y,
+							z,
+							res.status,
+							delay.as_millis()
+						));
+						sleep(delay).await;
+						attempt += 1;
+					}
+					None => return Err(Error::HttpStatus(res.status)),
+				},
+				status => return Err(Error::HttpStatus(status)),
+			}
+		}
+	}
+}
+
+#[async_trait(?Send)]
+impl TileSource for WebTileSource {
+	async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+		let data = self.get_tile_data(x, y, z, None).await?;
+		decode_tile(data.bytes, &self.options, x, y, z)
+	}
+
+	fn max_zoom(&self) -> Option<i32> {
+		self.max_zoom
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::TileCache;
+	use futures::executor::block_on;
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	fn response(status: &str, headers: &[&str], body: &str) -> String {
+		let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+		for header in headers {
+			response.push_str(header);
+			response.push_str("\r\n");
+		}
+		response.push_str("\r\n");
+		response.push_str(body);
+		response
+	}
+
+	// Serves one scripted response per connection, then stops listening and hands back the request lines it saw
+	fn serve(responses: Vec<String>) -> (WebTileSource, thread::JoinHandle<Vec<String>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let template = format!("http://{}/{{z}}/{{x}}/{{y}}.pbf", listener.local_addr().unwrap());
+		let server = thread::spawn(move || {
+			responses
+				.into_iter()
+				.map(|response| {
+					let (mut stream, _) = listener.accept().unwrap();
+					let mut reader = BufReader::new(stream.try_clone().unwrap());
+					let mut request = String::new();
+					reader.read_line(&mut request).unwrap();
+					let mut line = String::new();
+					while reader.read_line(&mut line).unwrap() > 2 {
+						line.clear();
+					}
+					stream.write_all(response.as_bytes()).unwrap();
+					request.trim().to_string()
+				})
+				.collect()
+		});
+		let source = WebTileSource::new(&template).with_retries(2, Duration::from_millis(1));
+		(source, server)
+	}
+
+	#[test]
+	fn http_dates() {
+		assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+		assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+		assert_eq!(parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"), Some(951_825_600));
+		assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+		assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+		assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+		assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+	}
+
+	#[test]
+	fn retry_after() {
+		let now = 784_111_777;
+		assert_eq!(parse_retry_after("120", now), Some(120));
+		assert_eq!(parse_retry_after(" 5 ", now), Some(5));
+		assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:50:37 GMT", now), Some(60));
+		// A date that's already gone means try again straight away
+		assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:48:37 GMT", now), Some(0));
+		assert_eq!(parse_retry_after("soon", now), None);
+	}
+
+	#[test]
+	fn retry_delays() {
+		let source = WebTileSource::new("").with_retries(3, Duration::from_millis(100));
+		assert_eq!(source.retry_delay(0, Some("2")), Some(Duration::from_secs(2)));
+		assert_eq!(source.retry_delay(0, Some("Thu, 01 Jan 1970 00:00:00 GMT")), Some(Duration::from_secs(0)));
+		assert_eq!(source.retry_delay(0, Some("3600")), None);
+		assert_eq!(source.retry_delay(3, Some("1")), None);
+		assert_eq!(source.retry_delay(3, None), None);
+
+		for attempt in 0..3 {
+			let backoff = Duration::from_millis(100 << attempt);
+			let delay = source.retry_delay(attempt, Some("not a delay")).unwrap();
+			assert!(delay >= backoff / 2 && delay <= backoff, "{:?} for attempt {}", delay, attempt);
+		}
+
+		let source = WebTileSource::new("").with_retries(20, Duration::from_secs(10));
+		assert!(source.retry_delay(10, None).unwrap() <= MAX_RETRY_DELAY);
+	}
+
+	#[test]
+	fn cache_control() {
+		assert_eq!(parse_max_age("public, max-age=3600"), Some(3600));
+		assert_eq!(parse_max_age("max-age=\"60\""), Some(60));
+		assert_eq!(parse_max_age("no-cache, max-age=3600"), Some(0));
+		assert_eq!(parse_max_age("public"), None);
+		assert!(parse_no_store("private, No-Store"));
+		assert!(!parse_no_store("no-cache"));
+	}
+
+	#[test]
+	fn urls() {
+		let source = WebTileSource::new("https://{s}.tiles.example/{z}/{x}/{-y}/{quadkey}.png");
+		assert_eq!(source.get_url(3, 5, 3), "https://c.tiles.example/3/3/2/213.png");
+		assert_eq!(source.get_url(0, 0, 0), "https://a.tiles.example/0/0/0/.png");
+		// The deepest zoom level doesn't overflow
+		let max = (1 << 30) - 1;
+		assert_eq!(
+			source.get_url(max, 0, 30),
+			format!("https://a.tiles.example/30/{}/{}/{}.png", max, max, "1".repeat(30))
+		);
+
+		let source = WebTileSource::new("https://{s}.example/{z}/{x}/{y}").with_subdomains(&["one", "two"]);
+		assert_eq!(source.get_url(0, 0, 1), "https://one.example/1/0/0");
+		assert_eq!(source.get_url(1, 0, 1), "https://two.example/1/1/0");
+		assert_eq!(source.get_url(1, 1, 1), "https://one.example/1/1/1");
+
+		let source = WebTileSource::new("https://example/{z}/{x}/{y}")
+			.with_query("access_token", "a b&c")
+			.with_query("v", "1");
+		assert_eq!(source.get_url(0, 0, 0), "https://example/0/0/0?access_token=a%20b%26c&v=1");
+		let source = WebTileSource::new("https://example/{z}/{x}/{y}?style=dark").with_query("key", "ü");
+		assert_eq!(source.get_url(0, 0, 0), "https://example/0/0/0?style=dark&key=%C3%BC");
+	}
+
+	#[test]
+	fn off_the_map() {
+		// Nothing's listening here, so this would fail if it made a request
+		let source = WebTileSource::new("http://127.0.0.1:1/{z}/{x}/{y}.pbf");
+		assert!(block_on(source.get_tile_data(4, 0, 2, None)).unwrap().bytes.is_empty());
+		assert!(block_on(source.get_tile_data(0, 0, 31, None)).unwrap().bytes.is_empty());
+	}
+
+	#[test]
+	fn missing_tiles_are_empty() {
+		let (source, server) = serve(vec![
+			response("404 Not Found", &[], "nothing here"),
+			response("204 No Content", &[], ""),
+		]);
+		assert!(block_on(source.get_tile_data(1, 2, 3, None)).unwrap().bytes.is_empty());
+		assert!(block_on(source.get_tile_data(4, 5, 6, None)).unwrap().bytes.is_empty());
+		assert_eq!(server.join().unwrap(), vec!["GET /3/1/2.pbf HTTP/1.1", "GET /6/4/5.pbf HTTP/1.1"]);
+	}
+
+	#[test]
+	fn retries_rate_limits_and_server_errors() {
+		let (source, server) = serve(vec![
+			response("429 Too Many Requests", &["Retry-After: 0"], ""),
+			response("503 Service Unavailable", &[], ""),
+			response("200 OK", &["ETag: \"abc\"", "Cache-Control: max-age=60"], "tile"),
+		]);
+		let data = block_on(source.get_tile_data(0, 0, 0, None)).unwrap();
+		assert_eq!(data.bytes, b"tile");
+		assert_eq!(data.etag.as_deref(), Some("\"abc\""));
+		assert_eq!(data.max_age, Some(60));
+		assert!(!data.no_store);
+		assert_eq!(server.join().unwrap().len(), 3);
+	}
+
+	#[test]
+	fn gives_up_after_max_retries() {
+		let (source, server) = serve(vec![response("500 Internal Server Error", &[], ""); 3]);
+		match block_on(source.get_tile_data(0, 0, 0, None)) {
+			Err(Error::HttpStatus(500)) => {}
+			other => panic!("Expected HTTP 500, got {:?}", other),
+		}
+		assert_eq!(server.join().unwrap().len(), 3);
+	}
+
+	fn assert_unauthorised(result: Result<TileData>) {
+		match result {
+			Err(Error::Unauthorized(401)) => {}
+			other => panic!("Expected HTTP 401, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn stops_asking_once_unauthorised() {
+		let (source, server) = serve(vec![response("401 Unauthorized", &[], "")]);
+		assert_unauthorised(block_on(source.get_tile_data(0, 0, 0, None)));
+		// The server's gone by now, so this would fail differently if it made a request
+		server.join().unwrap();
+		assert_unauthorised(block_on(source.get_tile_data(1, 0, 1, None)));
+	}
+
+	#[test]
+	fn cached_tiles_survive_unauthorised() {
+		let (source, server) = serve(vec![
+			response("200 OK", &["Cache-Control: max-age=0"], "tile"),
+			response("401 Unauthorized", &[], ""),
+		]);
+		let cache = TileCache::in_memory(source);
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"tile");
+
+		// Expired, so both of these go to the source and fail
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"tile");
+		server.join().unwrap();
+		assert_eq!(block_on(cache.get_tile_data(0, 0, 0, None)).unwrap().bytes, b"tile");
+		assert_unauthorised(block_on(cache.get_tile_data(1, 0, 1, None)));
+	}
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::io;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+	/// The request couldn't be sent or the response couldn't be read
+	Network(String),
+	/// A local file couldn't be read
+	Io(io::Error),
+	/// The server responded with a non-success status code
+	HttpStatus(u16),
+	/// The server rejected our credentials (HTTP 401 or 403)
+	Unauthorized(u16),
+	/// The tile payload couldn't be decompressed
+	Decompress(io::Error),
+	/// The tile payload isn't a valid protobuf message
+	Protobuf(quick_protobuf::Error),
+	/// The tile's geometry commands are malformed
+	Geometry(String),
+	/// A tileset's metadata is missing or malformed
+	Metadata(String),
+	/// A raster tile isn't a PNG or JPEG we can decode
+	Image(String),
+	/// A GeoJSON document couldn't be parsed or has an invalid geometry
+	GeoJson(String),
+	/// The ancestor an overzoomed tile is cut from failed to load
+	Ancestor(String),
+	/// A quadkey has digits other than 0 to 3 or is too long to be a tile
+	QuadKey(String),
+	/// An MBTiles query failed
+	#[cfg(not(target_arch = "wasm32"))]
+	Database(rusqlite::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Network(msg) => write!(f, "Network error: {}", msg),
+			Error::Io(err) => write!(f, "IO error: {}", err),
+			Error::HttpStatus(status) => write!(f, "Unexpected HTTP status: {}", status),
+			Error::Unauthorized(status) => write!(f, "Not authorised to fetch tiles (HTTP {}), check the access token", status),
+			Error::Decompress(err) => write!(f, "Failed to decompress tile: {}", err),
+			Error::Protobuf(err) => write!(f, "Failed to decode tile: {}", err),
+			Error::Geometry(msg) => write!(f, "Invalid tile geometry: {}", msg),
+			Error::Metadata(msg) => write!(f, "Invalid tileset metadata: {}", msg),
+			Error::Image(msg) => write!(f, "Failed to decode image: {}", msg),
+			Error::GeoJson(msg) => write!(f, "Invalid GeoJSON: {}", msg),
+			Error::Ancestor(msg) => write!(f, "Failed to load ancestor tile: {}", msg),
+			Error::QuadKey(msg) => write!(f, "Invalid quadkey: {}", msg),
+			#[cfg(not(target_arch = "wasm32"))]
+			Error::Database(err) => write!(f, "Database error: {}", err),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(err) => Some(err),
+			Error::Decompress(err) => Some(err),
+			Error::Protobuf(err) => Some(err),
+			#[cfg(not(target_arch = "wasm32"))]
+			Error::Database(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+impl From<quick_protobuf::Error> for Error {
+	fn from(err: quick_protobuf::Error) -> Self {
+		Error::Protobuf(err)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<rusqlite::Error> for Error {
+	fn from(err: rusqlite::Error) -> Self {
+		Error::Database(err)
+	}
+}
@@ -1,37 +1,56 @@
+use crate::error::Result;
 use crate::mesh::Mesh;
 use crate::protos::vector_tile::mod_Tile::Feature;
 use nalgebra as na;
 use std::f32::consts::PI;
 
-#[derive(Debug, Clone)]
+pub mod clip;
+pub mod mvt;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LonLat(na::Point2<f32>);
 
 impl LonLat {
 	pub fn new(lon: f32, lat: f32) -> Self {
 		Self(na::Point2::new(lon, lat))
 	}
+
+	pub fn lon(&self) -> f32 {
+		self.0.x
+	}
+
+	pub fn lat(&self) -> f32 {
+		self.0.y
+	}
+
+	/// The lon/lat as an x/y point, the way `lonlat_to_point` and `east_north` take it
+	pub fn as_point(&self) -> &na::Point2<f32> {
+		&self.0
+	}
 }
 
 impl Feature {
 	pub fn to_mesh(&self) -> Mesh {
-		Mesh {
-			vertices: vec![],
-			triangles: vec![],
-		}
+		Mesh::new()
+	}
+
+	/// The feature's shapes in tile coordinates, see `mvt::decode_geometry`
+	pub fn decode_geometry(&self) -> Result<mvt::Geometry> {
+		mvt::decode_geometry(self.type_pb, &self.geometry)
 	}
 }
 
 pub fn point_to_lonlat(point: &na::Point3<f32>) -> na::Point2<f32> {
 	let v = point.coords.normalize();
-	let mut lat = v.y.acos() - PI / 2.0;
-	let mut lon = v.x.atan2(-v.z);
+	let lat = v.y.acos() - PI / 2.0;
+	let lon = v.x.atan2(-v.z);
 	na::Point2::new(lon.to_degrees(), lat.to_degrees())
 }
 
-pub fn lonlat_to_point(ll: &na::Point2<f32>) -> na::Point3<f32> {
-	let rad = 1.0;
-	let lon = (ll.x).to_radians() as f32;
-	let lat = (ll.y - 90.0).to_radians() as f32;
+/// Position on the globe, `rad` is the distance from the centre where 1.0 is sea level
+pub fn lonlat_to_point(ll: &na::Point2<f32>, rad: f32) -> na::Point3<f32> {
+	let lon = (ll.x).to_radians();
+	let lat = (ll.y - 90.0).to_radians();
 
 	let x = -rad * lat.sin() * lon.sin();
 	let y = -rad * lat.cos();
@@ -40,16 +59,21 @@ pub fn lonlat_to_point(ll: &na::Point2<f32>) -> na::Point3<f32> {
 	na::Point3::new(x, y, z)
 }
 
-pub fn pixel_to_lonlat(p: &na::Point2<f32>, zoom: f32) -> na::Point2<f32> {
-	let tile_size = 0.5f32;
-	let c = tile_size * 2.0_f32.powi(zoom as i32);
-	let bc = c / 360.0;
-	let cc = c / (2.0 * PI);
+/// Unit vectors pointing east and north along the surface of the globe at a point
+pub fn east_north(ll: &na::Point2<f32>) -> (na::Vector3<f32>, na::Vector3<f32>) {
+	let lon = ll.x.to_radians();
+	let lat = ll.y.to_radians();
 
-	let e = c / 2.0;
-	let lon = (p.x - e) / bc;
-	let g = (p.y - e) / -cc;
-	let lat = (2.0f32 * g.exp().atan() - 0.5 * PI).to_degrees();
+	let east = na::Vector3::new(lon.cos(), 0.0, lon.sin());
+	let north = na::Vector3::new(-lat.sin() * lon.sin(), -lat.cos(), lat.sin() * lon.cos());
+	(east, north)
+}
 
-	na::Point2::new(lon, lat)
+/// Great circle distance between two points, in degrees of arc
+pub fn angular_distance(a: &LonLat, b: &LonLat) -> f32 {
+	let (lat0, lat1) = (a.lat().to_radians(), b.lat().to_radians());
+	let dlat = lat1 - lat0;
+	let dlon = (b.lon() - a.lon()).to_radians();
+	let h = (dlat / 2.0).sin().powi(2) + lat0.cos() * lat1.cos() * (dlon / 2.0).sin().powi(2);
+	(2.0 * h.sqrt().min(1.0).asin()).to_degrees()
 }
@@ -0,0 +1,334 @@
+use crate::geometry::mvt::{ring_area, Geometry, LineString, Point, Polygon};
+
+/// A box in tile coordinates, its edges count as inside
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+	pub min: Point,
+	pub max: Point,
+}
+
+impl Bounds {
+	/// A tile of `extent` units across with `buffer` more units all the way round it
+	pub fn tile(extent: u32, buffer: u32) -> Self {
+		let (extent, buffer) = (extent as i32, buffer as i32);
+		Self {
+			min: Point::new(-buffer, -buffer),
+			max: Point::new(extent + buffer, extent + buffer),
+		}
+	}
+
+	pub fn contains(&self, p: &Point) -> bool {
+		p.x >= self.min.x && p.y >= self.min.y && p.x <= self.max.x && p.y <= self.max.y
+	}
+
+	/// Whether a point lies on one of the box's edges, e.g. where a line was cut off
+	pub fn on_edge(&self, p: &Point) -> bool {
+		self.contains(p) && (p.x == self.min.x || p.y == self.min.y || p.x == self.max.x || p.y == self.max.y)
+	}
+}
+
+// Where the segment from `a` to `b` crosses `k` along `axis`, rounded to whole units off the axis
+fn intersect(a: &Point, b: &Point, axis: usize, k: i32) -> Point {
+	let t = (k as f64 - a[axis] as f64) / (b[axis] as f64 - a[axis] as f64);
+	let other = 1 - axis;
+	let mut p = *a;
+	p[axis] = k;
+	p[other] = (a[other] as f64 + (b[other] as f64 - a[other] as f64) * t).round() as i32;
+	p
+}
+
+// One side of Sutherland–Hodgman, keeping the part of a ring below or above `k` along `axis`
+fn clip_ring_side(ring: &[Point], axis: usize, k: i32, keep_below: bool) -> Vec<Point> {
+	let inside = |p: &Point| if keep_below { p[axis] <= k } else { p[axis] >= k };
+	let mut out = Vec::with_capacity(ring.len());
+	for (i, b) in ring.iter().enumerate() {
+		let a = &ring[(i + ring.len() - 1) % ring.len()];
+		if inside(a) != inside(b) {
+			out.push(intersect(a, b, axis, k));
+		}
+		if inside(b) {
+			out.push(*b);
+		}
+	}
+	out
+}
+
+/// The part of an open ring inside a box with Sutherland–Hodgman, `None` if nothing with any area is left
+///
+/// The ring keeps its winding. Where it leaves and comes back into the box it follows the edge of the box, so a
+/// concave ring can come out with parts joined by zero width slivers along the edge.
+pub fn clip_ring(ring: &[Point], bounds: &Bounds) -> Option<Vec<Point>> {
+	let mut ring = if ring.iter().all(|p| bounds.contains(p)) {
+		ring.to_vec()
+	} else {
+		let ring = clip_ring_side(ring, 0, bounds.min.x, false);
+		let ring = clip_ring_side(&ring, 0, bounds.max.x, true);
+		let ring = clip_ring_side(&ring, 1, bounds.min.y, false);
+		clip_ring_side(&ring, 1, bounds.max.y, true)
+	};
+	ring.dedup();
+	if ring.len() > 1 && ring.first() == ring.last() {
+		ring.pop();
+	}
+
+	if ring.len() < 3 || ring_area(&ring) == 0 {
+		None
+	} else {
+		Some(ring)
+	}
+}
+
+/// The part of a polygon inside a box, holes that end up outside it are dropped
+pub fn clip_polygon(polygon: &Polygon, bounds: &Bounds) -> Option<Polygon> {
+	Some(Polygon {
+		exterior: clip_ring(&polygon.exterior, bounds)?,
+		interiors: polygon.interiors.iter().filter_map(|ring| clip_ring(ring, bounds)).collect(),
+	})
+}
+
+/// The part of a segment inside a box with Liang–Barsky, ends that are already inside are left exactly as they were
+pub fn clip_segment(a: &Point, b: &Point, bounds: &Bounds) -> Option<(Point, Point)> {
+	let (ax, ay) = (a.x as f64, a.y as f64);
+	let (dx, dy) = (b.x as f64 - ax, b.y as f64 - ay);
+	let mut t0 = 0.0f64;
+	let mut t1 = 1.0f64;
+
+	let sides = [
+		(-dx, ax - bounds.min.x as f64),
+		(dx, bounds.max.x as f64 - ax),
+		(-dy, ay - bounds.min.y as f64),
+		(dy, bounds.max.y as f64 - ay),
+	];
+	for &(p, q) in &sides {
+		if p == 0.0 {
+			// Parallel to this side, so it's either all inside or all outside
+			if q < 0.0 {
+				return None;
+			}
+			continue;
+		}
+		let t = q / p;
+		if p < 0.0 {
+			t0 = t0.max(t);
+		} else {
+			t1 = t1.min(t);
+		}
+		if t0 > t1 {
+			return None;
+		}
+	}
+
+	let at = |t: f64| Point::new((ax + dx * t).round() as i32, (ay + dy * t).round() as i32);
+	let start = if t0 == 0.0 { *a } else { at(t0) };
+	let end = if t1 == 1.0 { *b } else { at(t1) };
+	Some((start, end))
+}
+
+/// The pieces of a line inside a box, a line that leaves and comes back is split in two
+pub fn clip_line(line: &[Point], bounds: &Bounds) -> Vec<LineString> {
+	clip_line_ends(line, bounds).into_iter().map(|(piece, _)| piece).collect()
+}
+
+/// The pieces of a line inside a box, with whether the box cut off the start and end of each
+///
+/// Ends that were already inside aren't cut, even if they lie on the edge of the box.
+pub fn clip_line_ends(line: &[Point], bounds: &Bounds) -> Vec<(LineString, [bool; 2])> {
+	let mut pieces: Vec<(LineString, [bool; 2])> = vec![];
+	// Whether the last piece carries on into the next segment
+	let mut inside = false;
+	for pair in line.windows(2) {
+		let (a, b) = match clip_segment(&pair[0], &pair[1], bounds) {
+			Some(segment) => segment,
+			None => {
+				inside = false;
+				continue;
+			}
+		};
+		match pieces.last_mut() {
+			Some((piece, _)) if inside && a == pair[0] => piece.push(b),
+			_ => pieces.push((vec![a, b], [a != pair[0], false])),
+		}
+		inside = b == pair[1];
+		if let Some((_, cut)) = pieces.last_mut() {
+			cut[1] = !inside;
+		}
+	}
+
+	for (piece, _) in &mut pieces {
+		piece.dedup();
+	}
+	pieces.retain(|(piece, _)| piece.len() >= 2);
+	pieces
+}
+
+/// The part of a feature's geometry inside a box, `None` if there's nothing left
+///
+/// A line cut into several pieces becomes a `MultiLineString`, everything else keeps its kind.
+pub fn clip_geometry(geometry: &Geometry, bounds: &Bounds) -> Option<Geometry> {
+	let clipped = match geometry {
+		Geometry::Point(point) => Geometry::Point(Some(*point).filter(|p| bounds.contains(p))?),
+		Geometry::MultiPoint(points) => {
+			Geometry::MultiPoint(points.iter().copied().filter(|p| bounds.contains(p)).collect())
+		}
+		Geometry::LineString(line) => {
+			let mut lines = clip_line(line, bounds);
+			if lines.len() == 1 {
+				Geometry::LineString(lines.remove(0))
+			} else {
+				Geometry::MultiLineString(lines)
+			}
+		}
+		Geometry::MultiLineString(lines) => {
+			Geometry::MultiLineString(lines.iter().flat_map(|line| clip_line(line, bounds)).collect())
+		}
+		Geometry::Polygon(polygon) => Geometry::Polygon(clip_polygon(polygon, bounds)?),
+		Geometry::MultiPolygon(polygons) => Geometry::MultiPolygon(
+			polygons
+				.iter()
+				.filter_map(|polygon| clip_polygon(polygon, bounds))
+				.collect(),
+		),
+	};
+
+	if clipped.point_count() == 0 {
+		None
+	} else {
+		Some(clipped)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn points(coords: &[(i32, i32)]) -> Vec<Point> {
+		coords.iter().map(|&(x, y)| Point::new(x, y)).collect()
+	}
+
+	fn segment(a: (i32, i32), b: (i32, i32)) -> Option<((i32, i32), (i32, i32))> {
+		let bounds = Bounds::tile(10, 0);
+		clip_segment(&Point::new(a.0, a.1), &Point::new(b.0, b.1), &bounds).map(|(a, b)| ((a.x, a.y), (b.x, b.y)))
+	}
+
+	#[test]
+	fn bounds() {
+		let bounds = Bounds::tile(10, 2);
+		assert_eq!(bounds.min, Point::new(-2, -2));
+		assert_eq!(bounds.max, Point::new(12, 12));
+		assert!(bounds.contains(&Point::new(-2, 12)));
+		assert!(!bounds.contains(&Point::new(-3, 5)));
+
+		assert!(bounds.on_edge(&Point::new(-2, 5)));
+		assert!(bounds.on_edge(&Point::new(12, 12)));
+		assert!(!bounds.on_edge(&Point::new(0, 5)));
+		// Outside isn't on the edge, even lined up with it
+		assert!(!bounds.on_edge(&Point::new(-2, 20)));
+	}
+
+	#[test]
+	fn segments() {
+		// Inside, even touching the edge, is left as it is
+		assert_eq!(segment((1, 1), (9, 9)), Some(((1, 1), (9, 9))));
+		assert_eq!(segment((0, 5), (10, 5)), Some(((0, 5), (10, 5))));
+
+		// Entering, leaving and going right through
+		assert_eq!(segment((-5, 5), (5, 5)), Some(((0, 5), (5, 5))));
+		assert_eq!(segment((5, 5), (5, 20)), Some(((5, 5), (5, 10))));
+		assert_eq!(segment((-10, -5), (20, 10)), Some(((0, 0), (10, 5))));
+
+		// Outside, alongside the edge and past a corner
+		assert_eq!(segment((-5, -5), (-1, 20)), None);
+		assert_eq!(segment((-5, 11), (20, 11)), None);
+		assert_eq!(segment((8, -5), (15, 2)), None);
+		// Crossing where the rounding puts it in a whole unit
+		assert_eq!(segment((-3, 0), (3, 3)), Some(((0, 2), (3, 3))));
+	}
+
+	#[test]
+	fn lines() {
+		let bounds = Bounds::tile(10, 0);
+
+		let inside = points(&[(1, 1), (5, 5), (10, 5)]);
+		assert_eq!(clip_line_ends(&inside, &bounds), vec![(inside.clone(), [false, false])]);
+
+		// Comes in, turns and goes out again
+		let through = points(&[(-5, 2), (5, 2), (5, 15)]);
+		assert_eq!(
+			clip_line_ends(&through, &bounds),
+			vec![(points(&[(0, 2), (5, 2), (5, 10)]), [true, true])]
+		);
+
+		// Leaves and comes back as two pieces, each cut where it meets the edge
+		let out_and_back = points(&[(2, 5), (15, 5), (15, 8), (8, 8)]);
+		assert_eq!(
+			clip_line_ends(&out_and_back, &bounds),
+			vec![(points(&[(2, 5), (10, 5)]), [false, true]), (points(&[(10, 8), (8, 8)]), [true, false])]
+		);
+		assert_eq!(clip_line(&out_and_back, &bounds), vec![points(&[(2, 5), (10, 5)]), points(&[(10, 8), (8, 8)])]);
+
+		assert!(clip_line(&points(&[(-5, -5), (-5, 20), (20, 20)]), &bounds).is_empty());
+		// A piece that only touches a corner has no length
+		assert!(clip_line(&points(&[(-5, 5), (5, -5)]), &bounds).is_empty());
+	}
+
+	#[test]
+	fn rings() {
+		let bounds = Bounds::tile(10, 0);
+
+		let inside = points(&[(1, 1), (9, 1), (9, 9)]);
+		assert_eq!(clip_ring(&inside, &bounds), Some(inside));
+
+		// Over the corner, following the edges of the box where it's cut
+		let corner = points(&[(5, 5), (15, 5), (15, 15), (5, 15)]);
+		assert_eq!(clip_ring(&corner, &bounds), Some(points(&[(5, 10), (5, 5), (10, 5), (10, 10)])));
+
+		// All the way round the box is the box
+		let around = points(&[(-5, -5), (15, -5), (15, 15), (-5, 15)]);
+		let clipped = clip_ring(&around, &bounds).unwrap();
+		assert_eq!(clipped.len(), 4);
+		assert!(clipped.iter().all(|p| bounds.on_edge(p)));
+		assert_eq!(ring_area(&clipped).abs(), ring_area(&points(&[(0, 0), (10, 0), (10, 10), (0, 10)])).abs());
+
+		assert_eq!(clip_ring(&points(&[(11, 0), (20, 0), (20, 10)]), &bounds), None);
+		// Only a sliver along the edge is left, with no area
+		assert_eq!(clip_ring(&points(&[(10, 0), (20, 0), (20, 10), (10, 10)]), &bounds), None);
+	}
+
+	#[test]
+	fn geometry() {
+		let bounds = Bounds::tile(10, 0);
+
+		assert_eq!(clip_geometry(&Geometry::Point(Point::new(20, 5)), &bounds), None);
+		assert_eq!(
+			clip_geometry(&Geometry::MultiPoint(points(&[(1, 1), (20, 5)])), &bounds),
+			Some(Geometry::MultiPoint(points(&[(1, 1)])))
+		);
+
+		// A line cut in two becomes a MultiLineString
+		let line = Geometry::LineString(points(&[(2, 5), (15, 5), (15, 8), (8, 8)]));
+		match clip_geometry(&line, &bounds) {
+			Some(Geometry::MultiLineString(lines)) => assert_eq!(lines.len(), 2),
+			other => panic!("Expected two lines, got {:?}", other),
+		}
+		assert_eq!(clip_geometry(&Geometry::LineString(points(&[(20, 0), (20, 10)])), &bounds), None);
+
+		// Holes outside the box are dropped, along with polygons that are entirely outside
+		let polygon = |exterior, interiors| Polygon {
+			exterior: points(exterior),
+			interiors,
+		};
+		let with_holes = polygon(
+			&[(0, 0), (20, 0), (20, 20), (0, 20)],
+			vec![points(&[(2, 2), (2, 4), (4, 4), (4, 2)]), points(&[(12, 12), (12, 14), (14, 14), (14, 12)])],
+		);
+		let outside = polygon(&[(30, 30), (40, 30), (40, 40)], vec![]);
+		match clip_geometry(&Geometry::MultiPolygon(vec![with_holes, outside.clone()]), &bounds) {
+			Some(Geometry::MultiPolygon(polygons)) => {
+				assert_eq!(polygons.len(), 1);
+				assert_eq!(polygons[0].interiors, vec![points(&[(2, 2), (2, 4), (4, 4), (4, 2)])]);
+			}
+			other => panic!("Expected one polygon, got {:?}", other),
+		}
+		assert_eq!(clip_geometry(&Geometry::Polygon(outside), &bounds), None);
+	}
+}
@@ -0,0 +1,605 @@
+use crate::error::{Error, Result};
+use crate::protos::vector_tile::mod_Tile::{Feature as RawFeature, GeomType, Layer as RawLayer, Value as RawValue};
+use nalgebra as na;
+use std::collections::HashMap;
+
+const MOVE_TO: u32 = 0x1;
+const LINE_TO: u32 = 0x2;
+const CLOSE_PATH: u32 = 0x7;
+
+/// A position in tile coordinates, 0 to the layer's extent across the tile with y pointing down
+pub type Point = na::Point2<i32>;
+
+pub type LineString = Vec<Point>;
+
+/// An exterior ring and the holes cut out of it
+///
+/// Rings are stored open, the last point isn't repeated. Exterior rings wind clockwise on screen and interior rings
+/// anticlockwise, as in the MVT spec.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Polygon {
+	pub exterior: Vec<Point>,
+	pub interiors: Vec<Vec<Point>>,
+}
+
+impl Polygon {
+	/// The exterior ring followed by the interior rings
+	pub fn rings(&self) -> impl Iterator<Item = &Vec<Point>> {
+		std::iter::once(&self.exterior).chain(&self.interiors)
+	}
+}
+
+/// A feature's geometry, decoded from its command stream
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+	Point(Point),
+	MultiPoint(Vec<Point>),
+	LineString(LineString),
+	MultiLineString(Vec<LineString>),
+	Polygon(Polygon),
+	MultiPolygon(Vec<Polygon>),
+}
+
+impl Geometry {
+	/// Every line and ring, and whether it's a ring that closes back on its first point
+	pub fn lines(&self) -> Vec<(&[Point], bool)> {
+		match self {
+			Geometry::Point(_) | Geometry::MultiPoint(_) => vec![],
+			Geometry::LineString(line) => vec![(line, false)],
+			Geometry::MultiLineString(lines) => lines.iter().map(|line| (&line[..], false)).collect(),
+			Geometry::Polygon(polygon) => polygon.rings().map(|ring| (&ring[..], true)).collect(),
+			Geometry::MultiPolygon(polygons) => polygons
+				.iter()
+				.flat_map(Polygon::rings)
+				.map(|ring| (&ring[..], true))
+				.collect(),
+		}
+	}
+
+	pub fn point_count(&self) -> usize {
+		match self {
+			Geometry::Point(_) => 1,
+			Geometry::MultiPoint(points) => points.len(),
+			_ => self.lines().iter().map(|(points, _)| points.len()).sum(),
+		}
+	}
+
+	/// The smallest and largest coordinates of every point
+	pub fn bounds(&self) -> (Point, Point) {
+		let mut min = Point::new(i32::MAX, i32::MAX);
+		let mut max = Point::new(i32::MIN, i32::MIN);
+		let mut add = |p: &Point| {
+			min = Point::new(min.x.min(p.x), min.y.min(p.y));
+			max = Point::new(max.x.max(p.x), max.y.max(p.y));
+		};
+		match self {
+			Geometry::Point(point) => add(point),
+			Geometry::MultiPoint(points) => points.iter().for_each(add),
+			_ => self.lines().iter().flat_map(|(points, _)| points.iter()).for_each(add),
+		}
+		(min, max)
+	}
+
+	/// The same shapes with every point moved by `f`
+	pub fn map(&self, f: impl Fn(&Point) -> Point) -> Geometry {
+		let line = |points: &Vec<Point>| points.iter().map(&f).collect::<Vec<_>>();
+		let polygon = |polygon: &Polygon| Polygon {
+			exterior: line(&polygon.exterior),
+			interiors: polygon.interiors.iter().map(line).collect(),
+		};
+		match self {
+			Geometry::Point(point) => Geometry::Point(f(point)),
+			Geometry::MultiPoint(points) => Geometry::MultiPoint(line(points)),
+			Geometry::LineString(points) => Geometry::LineString(line(points)),
+			Geometry::MultiLineString(lines) => Geometry::MultiLineString(lines.iter().map(line).collect()),
+			Geometry::Polygon(p) => Geometry::Polygon(polygon(p)),
+			Geometry::MultiPolygon(polygons) => Geometry::MultiPolygon(polygons.iter().map(polygon).collect()),
+		}
+	}
+}
+
+/// A property value, in any of the types a vector tile can hold
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	String(String),
+	Float(f32),
+	Double(f64),
+	Int(i64),
+	Uint(u64),
+	Sint(i64),
+	Bool(bool),
+}
+
+impl Value {
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::String(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	/// Any number as a double, so `admin_level` can be compared whichever type the tile used for it
+	pub fn as_f64(&self) -> Option<f64> {
+		match *self {
+			Value::Float(n) => Some(n as f64),
+			Value::Double(n) => Some(n),
+			Value::Int(n) | Value::Sint(n) => Some(n as f64),
+			Value::Uint(n) => Some(n as f64),
+			_ => None,
+		}
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		match self {
+			Value::Bool(b) => Some(*b),
+			_ => None,
+		}
+	}
+}
+
+pub type Properties = HashMap<String, Value>;
+
+/// A feature with its geometry and properties decoded
+#[derive(Clone, Debug, PartialEq)]
+pub struct Feature {
+	/// Unique within its layer if set, tiles can't tell an ID of 0 from no ID so it's `None` either way
+	pub id: Option<u64>,
+	pub geometry: Geometry,
+	pub properties: Properties,
+}
+
+// The points after a MoveTo, up to the next MoveTo
+struct Path {
+	points: Vec<Point>,
+	closed: bool,
+}
+
+fn invalid(message: &str) -> Error {
+	Error::Geometry(message.to_string())
+}
+
+// Read the next zigzag encoded parameter from a geometry command stream
+fn next_param(geometry: &mut impl Iterator<Item = u32>) -> Result<i32> {
+	let param = geometry.next().ok_or_else(|| invalid("Truncated command parameters"))?;
+	Ok((param >> 1) as i32 ^ -((param & 1) as i32))
+}
+
+// Move the cursor by the next pair of parameters
+fn next_point(geometry: &mut impl Iterator<Item = u32>, cursor: &mut Point) -> Result<Point> {
+	let dx = next_param(geometry)?;
+	let dy = next_param(geometry)?;
+	cursor.x = cursor.x.checked_add(dx).ok_or_else(|| invalid("Coordinate out of range"))?;
+	cursor.y = cursor.y.checked_add(dy).ok_or_else(|| invalid("Coordinate out of range"))?;
+	Ok(*cursor)
+}
+
+// Run the commands, checking each is allowed for the geometry type
+fn decode_paths(geom_type: GeomType, commands: &[u32]) -> Result<Vec<Path>> {
+	let mut geometry = commands.iter().copied();
+	let mut cursor = Point::origin();
+	let mut paths: Vec<Path> = vec![];
+
+	while let Some(cmdint) = geometry.next() {
+		let cmd = cmdint & 0x7;
+		let count = cmdint >> 3;
+
+		match cmd {
+			MOVE_TO => {
+				if count == 0 {
+					return Err(invalid("MoveTo with no points"));
+				}
+				if count > 1 && geom_type != GeomType::POINT {
+					return Err(invalid("MoveTo with more than one point"));
+				}
+				for _ in 0..count {
+					let point = next_point(&mut geometry, &mut cursor)?;
+					paths.push(Path {
+						points: vec![point],
+						closed: false,
+					});
+				}
+			}
+			LINE_TO => {
+				if geom_type == GeomType::POINT {
+					return Err(invalid("LineTo in a point geometry"));
+				}
+				if count == 0 {
+					return Err(invalid("LineTo with no points"));
+				}
+				let path = match paths.last_mut() {
+					Some(path) if !path.closed => path,
+					_ => return Err(invalid("LineTo without a MoveTo")),
+				};
+				for _ in 0..count {
+					path.points.push(next_point(&mut geometry, &mut cursor)?);
+				}
+			}
+			CLOSE_PATH => {
+				if geom_type != GeomType::POLYGON {
+					return Err(invalid("ClosePath outside a polygon"));
+				}
+				if count != 1 {
+					return Err(invalid("ClosePath with a count other than 1"));
+				}
+				match paths.last_mut() {
+					Some(path) if !path.closed => path.closed = true,
+					_ => return Err(invalid("ClosePath without a MoveTo")),
+				}
+			}
+			_ => return Err(Error::Geometry(format!("Unknown command {}", cmd))),
+		}
+	}
+
+	Ok(paths)
+}
+
+/// Twice the area of a ring by the surveyor's formula, positive for exterior rings
+///
+/// Tile coordinates point down, so this is positive when the ring winds clockwise on screen.
+pub fn ring_area(ring: &[Point]) -> i64 {
+	ring.iter()
+		.zip(ring.iter().cycle().skip(1))
+		.map(|(a, b)| a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64)
+		.sum()
+}
+
+/// Decode a feature's geometry commands into shapes of its type
+///
+/// Commands that don't fit the type, e.g. a `LineTo` in a point geometry or a polygon ring that isn't closed, are
+/// errors. Polygon rings with no area are dropped, and rings are grouped into polygons by winding order, each
+/// exterior ring starting a new one.
+pub fn decode_geometry(geom_type: GeomType, commands: &[u32]) -> Result<Geometry> {
+	if geom_type == GeomType::UNKNOWN {
+		return Err(invalid("Unknown geometry type"));
+	}
+	let paths = decode_paths(geom_type, commands)?;
+	if paths.is_empty() {
+		return Err(invalid("Geometry has no commands"));
+	}
+
+	match geom_type {
+		GeomType::UNKNOWN => unreachable!(),
+		GeomType::POINT => {
+			let mut points: Vec<Point> = paths.into_iter().map(|path| path.points[0]).collect();
+			if points.len() == 1 {
+				Ok(Geometry::Point(points.remove(0)))
+			} else {
+				Ok(Geometry::MultiPoint(points))
+			}
+		}
+		GeomType::LINESTRING => {
+			let mut lines = Vec::with_capacity(paths.len());
+			for path in paths {
+				if path.points.len() < 2 {
+					return Err(invalid("LineString with fewer than 2 points"));
+				}
+				lines.push(path.points);
+			}
+			if lines.len() == 1 {
+				Ok(Geometry::LineString(lines.remove(0)))
+			} else {
+				Ok(Geometry::MultiLineString(lines))
+			}
+		}
+		GeomType::POLYGON => {
+			let mut polygons: Vec<Polygon> = vec![];
+			for path in paths {
+				if !path.closed {
+					return Err(invalid("Polygon ring without a ClosePath"));
+				}
+				if path.points.len() < 3 {
+					return Err(invalid("Polygon ring with fewer than 3 points"));
+				}
+
+				let area = ring_area(&path.points);
+				if area > 0 {
+					polygons.push(Polygon {
+						exterior: path.points,
+						interiors: vec![],
+					});
+				} else if area < 0 {
+					match polygons.last_mut() {
+						Some(polygon) => polygon.interiors.push(path.points),
+						None => return Err(invalid("Polygon starts with an interior ring")),
+					}
+				}
+			}
+			if polygons.len() == 1 {
+				Ok(Geometry::Polygon(polygons.remove(0)))
+			} else {
+				Ok(Geometry::MultiPolygon(polygons))
+			}
+		}
+	}
+}
+
+/// Read whichever field of a layer's value is set
+pub fn decode_value(value: &RawValue) -> Result<Value> {
+	if let Some(s) = &value.string_value {
+		Ok(Value::String(s.to_string()))
+	} else if let Some(n) = value.float_value {
+		Ok(Value::Float(n))
+	} else if let Some(n) = value.double_value {
+		Ok(Value::Double(n))
+	} else if let Some(n) = value.int_value {
+		Ok(Value::Int(n))
+	} else if let Some(n) = value.uint_value {
+		Ok(Value::Uint(n))
+	} else if let Some(n) = value.sint_value {
+		Ok(Value::Sint(n))
+	} else if let Some(b) = value.bool_value {
+		Ok(Value::Bool(b))
+	} else {
+		Err(invalid("Property value with no type"))
+	}
+}
+
+/// Look up a feature's tags, pairs of indexes into the layer's keys and values
+///
+/// Values that couldn't be decoded are `None`, and only a feature that uses one is an error.
+pub fn decode_properties(tags: &[u32], keys: &[String], values: &[Option<Value>]) -> Result<Properties> {
+	if !tags.len().is_multiple_of(2) {
+		return Err(invalid("Odd number of feature tags"));
+	}
+	tags.chunks_exact(2)
+		.map(|tag| {
+			let key = keys.get(tag[0] as usize).ok_or_else(|| invalid("Tag key out of range"))?;
+			let value = values.get(tag[1] as usize).ok_or_else(|| invalid("Tag value out of range"))?;
+			let value = value.as_ref().ok_or_else(|| invalid("Property value with no type"))?;
+			Ok((key.clone(), value.clone()))
+		})
+		.collect()
+}
+
+/// Decode every feature in a layer, skipping any of unknown type as the spec allows
+///
+/// Each feature is decoded on its own, so one that's malformed doesn't stop the rest of the layer being used.
+pub fn decode_layer(layer: &RawLayer) -> Vec<Result<Feature>> {
+	let keys: Vec<String> = layer.keys.iter().map(|key| key.to_string()).collect();
+	let values: Vec<Option<Value>> = layer.values.iter().map(|value| decode_value(value).ok()).collect();
+
+	layer
+		.features
+		.iter()
+		.filter(|feature| feature.type_pb != GeomType::UNKNOWN)
+		.map(|feature| decode_feature(feature, &keys, &values))
+		.collect()
+}
+
+fn decode_feature(feature: &RawFeature, keys: &[String], values: &[Option<Value>]) -> Result<Feature> {
+	Ok(Feature {
+		id: Some(feature.id).filter(|&id| id != 0),
+		geometry: decode_geometry(feature.type_pb, &feature.geometry)?,
+		properties: decode_properties(&feature.tags, keys, values)?,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::borrow::Cow;
+
+	fn command(id: u32, count: u32) -> u32 {
+		(count << 3) | id
+	}
+
+	fn zigzag(n: i32) -> u32 {
+		((n << 1) ^ (n >> 31)) as u32
+	}
+
+	fn points(points: &[(i32, i32)]) -> Vec<Point> {
+		points.iter().map(|&(x, y)| Point::new(x, y)).collect()
+	}
+
+	// Commands for closed rings given as absolute points
+	fn rings(rings: &[&[(i32, i32)]]) -> Vec<u32> {
+		let mut commands = vec![];
+		let mut cursor = (0, 0);
+		for ring in rings {
+			let mut delta = |(x, y): (i32, i32)| {
+				let d = [zigzag(x - cursor.0), zigzag(y - cursor.1)];
+				cursor = (x, y);
+				d
+			};
+			commands.push(command(MOVE_TO, 1));
+			commands.extend(&delta(ring[0]));
+			commands.push(command(LINE_TO, ring.len() as u32 - 1));
+			for &p in &ring[1..] {
+				commands.extend(&delta(p));
+			}
+			commands.push(command(CLOSE_PATH, 1));
+		}
+		commands
+	}
+
+	fn assert_invalid(geom_type: GeomType, commands: &[u32], message: &str) {
+		match decode_geometry(geom_type, commands) {
+			Err(Error::Geometry(m)) => assert_eq!(m, message, "for {:?}", commands),
+			other => panic!("Expected '{}' for {:?}, got {:?}", message, commands, other),
+		}
+	}
+
+	#[test]
+	fn zigzag_params() {
+		for &n in &[0, 1, -1, 2, -2, 4095, -4096, i32::MAX, i32::MIN] {
+			let encoded = zigzag(n);
+			assert_eq!(next_param(&mut std::iter::once(encoded)).unwrap(), n, "{} encoded as {}", n, encoded);
+		}
+		assert_eq!(zigzag(-1), 1);
+		assert_eq!(zigzag(1), 2);
+	}
+
+	// The examples from section 4.3.5 of the spec
+	#[test]
+	fn spec_examples() {
+		assert_eq!(decode_geometry(GeomType::POINT, &[9, 50, 34]).unwrap(), Geometry::Point(Point::new(25, 17)));
+		assert_eq!(
+			decode_geometry(GeomType::POINT, &[17, 10, 14, 3, 9]).unwrap(),
+			Geometry::MultiPoint(points(&[(5, 7), (3, 2)]))
+		);
+		assert_eq!(
+			decode_geometry(GeomType::LINESTRING, &[9, 4, 4, 18, 0, 16, 16, 0]).unwrap(),
+			Geometry::LineString(points(&[(2, 2), (2, 10), (10, 10)]))
+		);
+		assert_eq!(
+			decode_geometry(GeomType::LINESTRING, &[9, 4, 4, 18, 0, 16, 16, 0, 9, 17, 17, 10, 4, 8]).unwrap(),
+			Geometry::MultiLineString(vec![points(&[(2, 2), (2, 10), (10, 10)]), points(&[(1, 1), (3, 5)])])
+		);
+		assert_eq!(
+			decode_geometry(GeomType::POLYGON, &[9, 6, 12, 18, 10, 12, 24, 44, 15]).unwrap(),
+			Geometry::Polygon(Polygon {
+				exterior: points(&[(3, 6), (8, 12), (20, 34)]),
+				interiors: vec![],
+			})
+		);
+
+		let multipolygon = [
+			9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15, 9, 22, 2, 26, 18, 0, 0, 18, 17, 0, 15, 9, 4, 13, 26, 0, 8, 8, 0, 0, 7,
+			15,
+		];
+		assert_eq!(
+			decode_geometry(GeomType::POLYGON, &multipolygon).unwrap(),
+			Geometry::MultiPolygon(vec![
+				Polygon {
+					exterior: points(&[(0, 0), (10, 0), (10, 10), (0, 10)]),
+					interiors: vec![],
+				},
+				Polygon {
+					exterior: points(&[(11, 11), (20, 11), (20, 20), (11, 20)]),
+					interiors: vec![points(&[(13, 13), (13, 17), (17, 17), (17, 13)])],
+				},
+			])
+		);
+	}
+
+	#[test]
+	fn rings_grouped_by_winding() {
+		let outer: &[(i32, i32)] = &[(0, 0), (100, 0), (100, 100), (0, 100)];
+		let hole: &[(i32, i32)] = &[(25, 25), (25, 75), (75, 75), (75, 25)];
+		let other: &[(i32, i32)] = &[(200, 0), (300, 0), (300, 100)];
+		let flat: &[(i32, i32)] = &[(0, 50), (50, 50), (100, 50)];
+
+		assert_eq!(
+			decode_geometry(GeomType::POLYGON, &rings(&[outer, hole])).unwrap(),
+			Geometry::Polygon(Polygon {
+				exterior: points(outer),
+				interiors: vec![points(hole)],
+			})
+		);
+
+		// A hole goes with the exterior ring before it, and rings with no area are dropped
+		assert_eq!(
+			decode_geometry(GeomType::POLYGON, &rings(&[outer, flat, other, hole])).unwrap(),
+			Geometry::MultiPolygon(vec![
+				Polygon {
+					exterior: points(outer),
+					interiors: vec![],
+				},
+				Polygon {
+					exterior: points(other),
+					interiors: vec![points(hole)],
+				},
+			])
+		);
+
+		assert_invalid(GeomType::POLYGON, &rings(&[hole, outer]), "Polygon starts with an interior ring");
+	}
+
+	#[test]
+	fn ring_areas() {
+		assert_eq!(ring_area(&points(&[(0, 0), (10, 0), (10, 10), (0, 10)])), 200);
+		assert_eq!(ring_area(&points(&[(0, 0), (0, 10), (10, 10), (10, 0)])), -200);
+		assert_eq!(ring_area(&points(&[(3, 6), (8, 12), (20, 34)])), 38);
+		assert_eq!(ring_area(&points(&[(0, 0), (5, 5), (10, 10)])), 0);
+		// Doesn't overflow at the far edges of the coordinate space
+		let big = i32::MAX;
+		assert_eq!(ring_area(&points(&[(0, 0), (big, 0), (big, big)])), big as i64 * big as i64);
+	}
+
+	#[test]
+	fn invalid_geometry() {
+		let max = zigzag(i32::MAX);
+		let cases: &[(GeomType, &[u32], &str)] = &[
+			(GeomType::UNKNOWN, &[9, 0, 0], "Unknown geometry type"),
+			(GeomType::POINT, &[], "Geometry has no commands"),
+			(GeomType::POINT, &[9, 0], "Truncated command parameters"),
+			(GeomType::POINT, &[1], "MoveTo with no points"),
+			(GeomType::LINESTRING, &[17, 0, 0, 2, 2], "MoveTo with more than one point"),
+			(GeomType::POINT, &[9, 0, 0, 10, 2, 2], "LineTo in a point geometry"),
+			(GeomType::LINESTRING, &[9, 0, 0, 2], "LineTo with no points"),
+			(GeomType::LINESTRING, &[10, 2, 2], "LineTo without a MoveTo"),
+			(GeomType::POLYGON, &[9, 0, 0, 18, 2, 0, 0, 2, 15, 10, 2, 2], "LineTo without a MoveTo"),
+			(GeomType::LINESTRING, &[9, 0, 0, 10, 2, 2, 15], "ClosePath outside a polygon"),
+			(GeomType::POLYGON, &[9, 0, 0, 18, 2, 0, 0, 2, 23], "ClosePath with a count other than 1"),
+			(GeomType::POLYGON, &[15], "ClosePath without a MoveTo"),
+			(GeomType::POLYGON, &[9, 0, 0, 18, 2, 0, 0, 2, 15, 15], "ClosePath without a MoveTo"),
+			(GeomType::POINT, &[9, 0, 0, 11, 2, 2], "Unknown command 3"),
+			(GeomType::LINESTRING, &[9, max, 0, 10, 2, 0], "Coordinate out of range"),
+			(GeomType::LINESTRING, &[9, 0, 0], "LineString with fewer than 2 points"),
+			(GeomType::POLYGON, &[9, 0, 0, 18, 2, 0, 0, 2], "Polygon ring without a ClosePath"),
+			(GeomType::POLYGON, &[9, 0, 0, 10, 2, 0, 15], "Polygon ring with fewer than 3 points"),
+		];
+		for (geom_type, commands, message) in cases {
+			assert_invalid(*geom_type, commands, message);
+		}
+	}
+
+	#[test]
+	fn properties() {
+		let keys = vec!["name".to_string(), "height".to_string()];
+		let values = vec![Some(Value::String("Tower".into())), Some(Value::Uint(300)), None];
+
+		let properties = decode_properties(&[0, 0, 1, 1], &keys, &values).unwrap();
+		assert_eq!(properties["name"].as_str(), Some("Tower"));
+		assert_eq!(properties["height"].as_f64(), Some(300.0));
+
+		assert!(decode_properties(&[0], &keys, &values).is_err());
+		assert!(decode_properties(&[2, 0], &keys, &values).is_err());
+		assert!(decode_properties(&[0, 3], &keys, &values).is_err());
+		assert!(decode_properties(&[0, 2], &keys, &values).is_err());
+		assert!(decode_value(&RawValue::default()).is_err());
+	}
+
+	#[test]
+	fn bad_features_dont_spoil_the_layer() {
+		let feature = |id, type_pb, tags: Vec<u32>, geometry: Vec<u32>| RawFeature {
+			id,
+			tags,
+			type_pb,
+			geometry,
+		};
+		let layer = RawLayer {
+			version: 2,
+			name: Cow::Borrowed("test"),
+			features: vec![
+				feature(1, GeomType::POINT, vec![0, 0], vec![9, 50, 34]),
+				// A LineTo in a point
+				feature(2, GeomType::POINT, vec![], vec![9, 0, 0, 10, 2, 2]),
+				// Unknown types are skipped
+				feature(3, GeomType::UNKNOWN, vec![], vec![9, 0, 0]),
+				// Uses the value with no type
+				feature(4, GeomType::POINT, vec![0, 1], vec![9, 2, 2]),
+				feature(0, GeomType::POINT, vec![], vec![9, 2, 2]),
+			],
+			keys: vec![Cow::Borrowed("kind")],
+			values: vec![
+				RawValue {
+					string_value: Some(Cow::Borrowed("peak")),
+					..RawValue::default()
+				},
+				RawValue::default(),
+			],
+			extent: 4096,
+		};
+
+		let features = decode_layer(&layer);
+		assert_eq!(features.len(), 4);
+		let first = features[0].as_ref().unwrap();
+		assert_eq!(first.id, Some(1));
+		assert_eq!(first.geometry, Geometry::Point(Point::new(25, 17)));
+		assert_eq!(first.properties["kind"].as_str(), Some("peak"));
+		assert!(features[1].is_err());
+		assert!(features[2].is_err());
+		assert_eq!(features[3].as_ref().unwrap().id, None);
+	}
+}
@@ -1,42 +1,271 @@
-use crate::data::WebTileSource;
-use crate::geometry::LonLat;
+use crate::data::{TileSource, WebTileSource};
+use crate::error::Result;
+use crate::geometry::{angular_distance, LonLat};
+use crate::mercator::{lonlat_to_tile, TileCoord};
 use crate::tile::Tile;
-use std::collections::HashMap;
+use futures::future::poll_fn;
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
 
-type TileCoord = (i32, i32, i32);
+mod loader;
+mod lru;
+
+pub(crate) use loader::fetch_tile;
+pub use loader::{Priority, TileLoader};
+pub use lru::TileLru;
+
+// Enough for a few hundred detailed tiles
+const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+// Enough to fetch a whole screen of tiles in one go over HTTP/2
+const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// The part of the globe the camera can see
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileView {
+	/// Point under the centre of the screen
+	pub center: LonLat,
+	/// Distance from the centre to the furthest visible point, in degrees of arc
+	pub radius: f32,
+	/// Zoom level to load tiles at
+	pub zoom: i32,
+}
+
+impl TileView {
+	/// True if any part of the tile is within the view
+	pub fn contains(&self, coord: &TileCoord) -> bool {
+		let (center, radius) = tile_extent(coord);
+		angular_distance(&self.center, &center) - radius <= self.radius
+	}
+
+	/// How soon a tile should be loaded, or `None` if it's out of view
+	pub fn priority(&self, coord: &TileCoord) -> Option<Priority> {
+		let (center, radius) = tile_extent(coord);
+		let distance = angular_distance(&self.center, &center);
+		if distance - radius > self.radius {
+			return None;
+		}
+		Some(Priority { zoom: coord.z, distance })
+	}
+
+	/// Every tile at the view's zoom level that's at least partly visible
+	pub fn tiles(&self) -> Vec<TileCoord> {
+		let z = self.zoom;
+		let n = 1 << z;
+		let lat = self.center.lat();
+
+		// Rows covering the latitudes in view
+		let row = |lat: f32| TileCoord::from_lonlat(&LonLat::new(0.0, lat), z).y;
+		let y0 = row(lat + self.radius);
+		let y1 = row(lat - self.radius);
+
+		// Columns covering the longitudes in view, all of them if a pole is visible
+		let (x0, x1) = if lat.abs() + self.radius >= 90.0 {
+			(0, n - 1)
+		} else {
+			let ratio = self.radius.to_radians().sin() / lat.to_radians().cos();
+			if ratio >= 1.0 {
+				(0, n - 1)
+			} else {
+				let dlon = ratio.asin().to_degrees();
+				let x0 = lonlat_to_tile(&LonLat::new(self.center.lon() - dlon, 0.0), z).0.floor() as i32;
+				let x1 = lonlat_to_tile(&LonLat::new(self.center.lon() + dlon, 0.0), z).0.floor() as i32;
+				if x1 - x0 >= n - 1 {
+					(0, n - 1)
+				} else {
+					(x0, x1)
+				}
+			}
+		};
+
+		let mut tiles = vec![];
+		for y in y0..=y1 {
+			for x in x0..=x1 {
+				let coord = TileCoord::new(x, y, z).wrapped();
+				if self.contains(&coord) {
+					tiles.push(coord);
+				}
+			}
+		}
+		tiles
+	}
+}
+
+// Centre of a tile and the distance to its furthest corner, in degrees of arc
+fn tile_extent(coord: &TileCoord) -> (LonLat, f32) {
+	let center = coord.center();
+	let (sw, ne) = coord.bounds();
+	let radius = [(sw.lon(), sw.lat()), (ne.lon(), sw.lat()), (sw.lon(), ne.lat()), (ne.lon(), ne.lat())]
+		.iter()
+		.map(|&(lon, lat)| angular_distance(&center, &LonLat::new(lon, lat)))
+		.fold(0.0, f32::max);
+	(center, radius)
+}
 
-#[derive(Debug, Default)]
 pub struct Globe {
-	source: WebTileSource,
-	tile_queue: Vec<(i32, i32, i32)>,
-	tiles: HashMap<TileCoord, Tile>,
+	source: Rc<dyn TileSource>,
+	loader: TileLoader,
+	tiles: TileLru,
+	evicted: Vec<TileCoord>,
+	view: Option<TileView>,
+}
+
+impl Default for Globe {
+	fn default() -> Self {
+		Self::new(Box::new(WebTileSource::mapbox("")))
+	}
+}
+
+impl fmt::Debug for Globe {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Globe")
+			.field("tiles_in_flight", &self.loader.in_flight())
+			.field("tiles", &self.tiles)
+			.field("evicted", &self.evicted)
+			.field("view", &self.view)
+			.finish()
+	}
 }
 
 impl Globe {
-	pub fn new(token: &str) -> Self {
+	pub fn new(source: Box<dyn TileSource>) -> Self {
+		let source: Rc<dyn TileSource> = source.into();
 		Self {
-			tile_queue: vec![],
-			tiles: HashMap::default(),
-			source: WebTileSource::new(token),
+			loader: TileLoader::new(source.clone(), DEFAULT_MAX_IN_FLIGHT),
+			tiles: TileLru::new(DEFAULT_MEMORY_BUDGET),
+			evicted: vec![],
+			view: None,
+			source,
+		}
+	}
+
+	/// Limit how many tile requests can be in flight at once
+	pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+		self.loader.set_max_in_flight(max_in_flight);
+		self
+	}
+
+	/// Limit how many bytes of tile meshes are kept around
+	pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+		self.set_memory_budget(bytes);
+		self
+	}
+
+	pub fn set_memory_budget(&mut self, bytes: usize) {
+		let evicted = self.tiles.set_budget(bytes);
+		self.add_evicted(evicted);
+	}
+
+	pub fn tiles(&self) -> impl Iterator<Item = (&TileCoord, &Tile)> {
+		self.tiles.iter()
+	}
+
+	/// Mark a tile as recently used so it's the last to be evicted
+	pub fn touch_tile(&mut self, coord: &TileCoord) {
+		self.tiles.touch(coord);
+	}
+
+	/// Coordinates of tiles evicted since the last call, so anything built from them can be dropped too
+	pub fn take_evicted(&mut self) -> Vec<TileCoord> {
+		std::mem::take(&mut self.evicted)
+	}
+
+	fn insert_tile(&mut self, coord: TileCoord, tile: Tile) {
+		let evicted = self.tiles.insert(coord, tile);
+		self.add_evicted(evicted);
+	}
+
+	// `set_view` won't queue evicted tiles again until the view changes, so anything still on screen is requested
+	// again straight away
+	fn add_evicted(&mut self, evicted: Vec<TileCoord>) {
+		if let Some(view) = &self.view {
+			for coord in &evicted {
+				if coord.z == view.zoom {
+					if let Some(priority) = view.priority(coord) {
+						self.loader.request(*coord, priority);
+					}
+				}
+			}
 		}
+		self.evicted.extend(evicted);
 	}
 
-	pub fn tiles(&self) -> &HashMap<TileCoord, Tile> {
-		&self.tiles
+	/// Deepest zoom level the source has tiles for, deeper tiles are cut out of their ancestor at this level
+	pub fn max_zoom(&self) -> Option<i32> {
+		self.source.max_zoom()
 	}
 
+	pub fn view(&self) -> Option<&TileView> {
+		self.view.as_ref()
+	}
+
+	/// Update what the camera can see
+	///
+	/// Visible tiles that aren't loaded yet are queued, closest to the centre
+	/// first. Anything queued or loading that's no longer visible is cancelled.
+	pub fn set_view(&mut self, view: TileView) {
+		if self.view.as_ref() == Some(&view) {
+			return;
+		}
+		self.loader.reprioritise(|coord| view.priority(coord));
+		let tiles = view.tiles();
+		self.view = Some(view);
+		for coord in tiles {
+			self.touch_tile(&coord);
+			self.queue_tile(coord.x, coord.y, coord.z);
+		}
+	}
+
+	/// Queue a tile to be loaded, unless it's already loaded, on its way, out of view or off the map
+	///
+	/// Tiles past the source's max zoom are built straight away if their ancestor is already loaded.
 	pub fn queue_tile(&mut self, x: i32, y: i32, z: i32) {
-		self.tile_queue.push((x, y, z));
+		let coord = TileCoord::new(x, y, z);
+		if !coord.is_valid() || self.tiles.contains(&coord) {
+			return;
+		}
+		if let Some(max_zoom) = self.max_zoom().filter(|max_zoom| z > *max_zoom) {
+			let parent = coord.ancestor(max_zoom);
+			if let Some(tile) = self.tiles.peek(&parent).map(|tile| tile.overzoom(parent, coord)) {
+				self.insert_tile(coord, tile);
+				return;
+			}
+		}
+		let priority = match &self.view {
+			Some(view) => match view.priority(&coord) {
+				Some(priority) => priority,
+				None => return,
+			},
+			None => Priority { zoom: z, distance: 0.0 },
+		};
+		self.loader.request(coord, priority);
 	}
 
-	pub async fn update(&mut self) {
-		if let Some(coord) = self.tile_queue.pop() {
-			let tile = self.source.get_tile(coord.0, coord.1, coord.2).await;
-			self.tiles.insert(coord, tile);
+	/// True when nothing is queued or loading
+	pub fn is_idle(&self) -> bool {
+		self.loader.is_idle()
+	}
+
+	/// Poll for the next queued tile to finish loading and add it to the globe
+	///
+	/// Returns `Pending` while idle, and is woken when another tile is queued.
+	pub fn poll_update(&mut self, cx: &mut Context) -> Poll<(TileCoord, Result<()>)> {
+		self.loader.poll_next(cx).map(|(coord, result)| {
+			let result = result.map(|tile| self.insert_tile(coord, tile));
+			(coord, result)
+		})
+	}
+
+	/// Wait for the next queued tile to finish loading. Returns straight away if nothing is queued.
+	pub async fn update(&mut self) -> Result<()> {
+		if self.is_idle() {
+			return Ok(());
 		}
+		let (_, result) = poll_fn(|cx| self.poll_update(cx)).await;
+		result
 	}
 
-	pub async fn get_tiles(&self, ll: &LonLat) -> Vec<Tile> {
+	pub async fn get_tiles(&self, ll: &LonLat) -> Result<Vec<Tile>> {
 		println!("Fetching tile {:?}", ll);
 
 		let zoom = 1;
@@ -44,19 +273,64 @@ impl Globe {
 		let n = 2i32.pow(zoom);
 		for y in 0..n {
 			for x in 0..n {
-				tiles.push(self.source.get_tile(x, y, zoom as i32).await);
+				tiles.push(self.source.get_tile(x, y, zoom as i32).await?);
 			}
 		}
 
-		tiles
+		Ok(tiles)
+	}
+
+	pub async fn get_tile(&self, x: i32, y: i32, zoom: i32) -> Result<Tile> {
+		fetch_tile(&*self.source, TileCoord::new(x, y, zoom)).await
 	}
 
-	pub async fn get_tile(&self, x: i32, y: i32, zoom: i32) -> Tile {
-		self.source.get_tile(x, y, zoom).await
+	pub async fn load_tile(&mut self, x: i32, y: i32, z: i32) -> Result<()> {
+		let coord = TileCoord::new(x, y, z);
+		let tile = fetch_tile(&*self.source, coord).await?;
+		self.insert_tile(coord, tile);
+		Ok(())
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mesh::Texture;
+	use async_trait::async_trait;
+	use futures::executor::block_on;
+
+	// Every tile is the same one pixel image, so they're all the same size
+	struct RasterSource;
+
+	#[async_trait(?Send)]
+	impl TileSource for RasterSource {
+		async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+			let texture = Texture {
+				width: 1,
+				height: 1,
+				pixels: vec![0; 4],
+			};
+			Ok(Tile::from_raster(texture, x, y, z))
+		}
+	}
+
+	#[test]
+	fn visible_tiles_are_loaded_again_after_eviction() {
+		let mut globe = Globe::new(Box::new(RasterSource));
+		globe.set_view(TileView {
+			center: LonLat::new(0.0, 0.0),
+			radius: 180.0,
+			zoom: 1,
+		});
+		while !globe.is_idle() {
+			block_on(globe.update()).unwrap();
+		}
+		assert_eq!(globe.tiles().count(), 4);
 
-	pub async fn load_tile(&mut self, x: i32, y: i32, z: i32) {
-		let tile = self.source.get_tile(x, y, z).await;
-		// TODO something with tile
+		let size = globe.tiles().next().unwrap().1.byte_size();
+		globe.set_memory_budget(size * 2);
+		assert_eq!(globe.take_evicted().len(), 2);
+		// The view hasn't changed, but the evicted tiles are still on screen
+		assert!(!globe.is_idle());
 	}
 }
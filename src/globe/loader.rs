@@ -0,0 +1,457 @@
+use crate::data::TileSource;
+use crate::error::{Error, Result};
+use crate::mercator::TileCoord;
+use crate::tile::Tile;
+use futures::future::{abortable, AbortHandle, FutureExt, LocalBoxFuture, Shared};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+type TileLoad = (TileCoord, Result<Tile>);
+// Shared between every overzoomed tile cut from the same ancestor, so it's only fetched once
+type AncestorLoad = Shared<LocalBoxFuture<'static, std::result::Result<Rc<Tile>, Rc<Error>>>>;
+
+/// Order in which tiles are fetched, lower loads first
+///
+/// Coarser zoom levels always go before finer ones, then tiles closest to the
+/// centre of the view.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Priority {
+	pub zoom: i32,
+	pub distance: f32,
+}
+
+/// Fetches tiles from a source, keeping up to `max_in_flight` requests going at once
+///
+/// A coordinate that's already queued or being fetched isn't requested again.
+/// Queued tiles are started in priority order, and any tile can be cancelled
+/// before it completes. Tiles past the source's max zoom are cut from one
+/// shared fetch of their ancestor, kept until none of them are pending.
+pub struct TileLoader {
+	source: Rc<dyn TileSource>,
+	// Sorted so the highest priority tile is at the end
+	queue: Vec<(Priority, TileCoord)>,
+	pending: HashSet<TileCoord>,
+	in_flight: FuturesUnordered<LocalBoxFuture<'static, (TileCoord, Option<Result<Tile>>)>>,
+	handles: HashMap<TileCoord, AbortHandle>,
+	ancestors: HashMap<TileCoord, AncestorLoad>,
+	max_in_flight: usize,
+	waker: Option<Waker>,
+}
+
+/// Fetch a tile, building it from its ancestor if it's deeper than the source goes
+pub async fn fetch_tile(source: &dyn TileSource, coord: TileCoord) -> Result<Tile> {
+	match source.max_zoom() {
+		Some(max_zoom) if coord.z > max_zoom => {
+			let parent = coord.ancestor(max_zoom);
+			let tile = source.get_tile(parent.x, parent.y, parent.z).await?;
+			Ok(tile.overzoom(parent, coord))
+		}
+		_ => source.get_tile(coord.x, coord.y, coord.z).await,
+	}
+}
+
+fn compare(a: &Priority, b: &Priority) -> Ordering {
+	a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+impl TileLoader {
+	pub fn new(source: Rc<dyn TileSource>, max_in_flight: usize) -> Self {
+		Self {
+			source,
+			queue: vec![],
+			pending: HashSet::new(),
+			in_flight: FuturesUnordered::new(),
+			handles: HashMap::new(),
+			ancestors: HashMap::new(),
+			max_in_flight: max_in_flight.max(1),
+			waker: None,
+		}
+	}
+
+	pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+		self.max_in_flight = max_in_flight.max(1);
+	}
+
+	/// Queue a tile to be fetched, returns false if it's already queued or in flight
+	pub fn request(&mut self, coord: TileCoord, priority: Priority) -> bool {
+		if !self.pending.insert(coord) {
+			return false;
+		}
+		// Goes in front of anything with the same priority, so equal tiles load first come first served
+		let idx = self.queue.partition_point(|(p, _)| compare(p, &priority) == Ordering::Greater);
+		self.queue.insert(idx, (priority, coord));
+		self.wake();
+		true
+	}
+
+	/// Recalculate the priority of every queued and in flight tile
+	///
+	/// Tiles given no priority are cancelled. Requests already in flight are
+	/// aborted, which drops the underlying fetch.
+	pub fn reprioritise<F>(&mut self, priority: F)
+	where
+		F: Fn(&TileCoord) -> Option<Priority>,
+	{
+		let pending = &mut self.pending;
+		self.queue.retain_mut(|(p, coord)| match priority(coord) {
+			Some(new) => {
+				*p = new;
+				true
+			}
+			None => {
+				pending.remove(coord);
+				false
+			}
+		});
+		self.queue.sort_by(|(a, _), (b, _)| compare(b, a));
+
+		let cancelled: Vec<TileCoord> = self.handles.keys().filter(|c| priority(c).is_none()).copied().collect();
+		for coord in cancelled {
+			self.cancel(&coord);
+		}
+		self.prune_ancestors();
+
+		// Aborting frees up slots for queued tiles
+		self.wake();
+	}
+
+	/// Stop loading a tile, returns false if it wasn't queued or in flight
+	pub fn cancel(&mut self, coord: &TileCoord) -> bool {
+		if !self.pending.remove(coord) {
+			return false;
+		}
+		if let Some(handle) = self.handles.remove(coord) {
+			handle.abort();
+		} else {
+			self.queue.retain(|(_, c)| c != coord);
+		}
+		self.prune_ancestors();
+		true
+	}
+
+	pub fn is_pending(&self, coord: &TileCoord) -> bool {
+		self.pending.contains(coord)
+	}
+
+	/// Number of tiles being fetched right now
+	pub fn in_flight(&self) -> usize {
+		self.handles.len()
+	}
+
+	pub fn is_idle(&self) -> bool {
+		self.pending.is_empty()
+	}
+
+	fn wake(&mut self) {
+		if let Some(waker) = self.waker.take() {
+			waker.wake();
+		}
+	}
+
+	// The load of an ancestor of overzoomed tiles, started by the first of them to need it
+	fn load_ancestor(&mut self, coord: TileCoord) -> AncestorLoad {
+		let source = self.source.clone();
+		self.ancestors
+			.entry(coord)
+			.or_insert_with(|| {
+				let load: LocalBoxFuture<_> = Box::pin(async move {
+					let tile = source.get_tile(coord.x, coord.y, coord.z).await;
+					tile.map(Rc::new).map_err(Rc::new)
+				});
+				load.shared()
+			})
+			.clone()
+	}
+
+	// Forget ancestors that no pending tile is cut from
+	fn prune_ancestors(&mut self) {
+		if let Some(max_zoom) = self.source.max_zoom() {
+			let pending = &self.pending;
+			self.ancestors
+				.retain(|ancestor, _| pending.iter().any(|c| c.z > max_zoom && c.ancestor(max_zoom) == *ancestor));
+		}
+	}
+
+	// Start fetching queued tiles until we hit the limit
+	fn start_requests(&mut self) {
+		while self.handles.len() < self.max_in_flight {
+			let coord = match self.queue.pop() {
+				Some((_, coord)) => coord,
+				None => break,
+			};
+			let load: LocalBoxFuture<_> = match self.source.max_zoom() {
+				Some(max_zoom) if coord.z > max_zoom => {
+					let parent = coord.ancestor(max_zoom);
+					let ancestor = self.load_ancestor(parent);
+					Box::pin(async move {
+						match ancestor.await {
+							Ok(tile) => Ok(tile.overzoom(parent, coord)),
+							Err(err) => Err(Error::Ancestor(format!("{}: {}", parent, err))),
+						}
+					})
+				}
+				_ => {
+					let source = self.source.clone();
+					Box::pin(async move { source.get_tile(coord.x, coord.y, coord.z).await })
+				}
+			};
+			let (load, handle) = abortable(load);
+			self.handles.insert(coord, handle);
+			self.in_flight.push(Box::pin(async move { (coord, load.await.ok()) }));
+		}
+	}
+
+	/// Poll for the next tile to finish loading, in whatever order they complete
+	///
+	/// Cancelled tiles are never returned. Returns `Pending` while idle and
+	/// wakes up once something is requested.
+	pub fn poll_next(&mut self, cx: &mut Context) -> Poll<TileLoad> {
+		loop {
+			self.start_requests();
+
+			match self.in_flight.poll_next_unpin(cx) {
+				Poll::Ready(Some((coord, Some(result)))) => {
+					self.handles.remove(&coord);
+					self.pending.remove(&coord);
+					self.prune_ancestors();
+					return Poll::Ready((coord, result));
+				}
+				// Aborted, it was already forgotten when it was cancelled
+				Poll::Ready(Some((_, None))) => continue,
+				Poll::Ready(None) | Poll::Pending => {
+					// Also woken by new requests, which might fit alongside what's in flight
+					self.waker = Some(cx.waker().clone());
+					return Poll::Pending;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use async_trait::async_trait;
+	use futures::executor::block_on;
+	use futures::future::poll_fn;
+	use futures::task::noop_waker;
+	use std::cell::RefCell;
+
+	// Hands out empty tiles down to zoom 1, remembering what it was asked for
+	#[derive(Default)]
+	struct CountingSource {
+		fetched: RefCell<Vec<TileCoord>>,
+	}
+
+	#[async_trait(?Send)]
+	impl TileSource for CountingSource {
+		async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+			self.fetched.borrow_mut().push(TileCoord::new(x, y, z));
+			Ok(Tile::new())
+		}
+
+		fn max_zoom(&self) -> Option<i32> {
+			Some(1)
+		}
+	}
+
+	#[test]
+	fn overzoomed_tiles_share_their_ancestor() {
+		let source = Rc::new(CountingSource::default());
+		let mut loader = TileLoader::new(source.clone(), 2);
+
+		// Every z3 tile under (0, 0, 1), plus one under (1, 0, 1)
+		let mut tiles: Vec<TileCoord> = (0..16).map(|i| TileCoord::new(i % 4, i / 4, 3)).collect();
+		tiles.push(TileCoord::new(4, 0, 3));
+		for (i, coord) in tiles.iter().enumerate() {
+			let priority = Priority { zoom: 3, distance: i as f32 };
+			assert!(loader.request(*coord, priority));
+		}
+
+		let mut loaded = vec![];
+		while !loader.is_idle() {
+			let (coord, result) = block_on(poll_fn(|cx| loader.poll_next(cx)));
+			assert!(result.is_ok());
+			loaded.push(coord);
+		}
+		loaded.sort();
+		tiles.sort();
+		assert_eq!(loaded, tiles);
+		assert_eq!(*source.fetched.borrow(), vec![TileCoord::new(0, 0, 1), TileCoord::new(1, 0, 1)]);
+		assert!(loader.ancestors.is_empty());
+	}
+
+	// Records the order tiles are asked for in, and holds on to each one until it's released
+	#[derive(Default)]
+	struct GatedSource {
+		started: RefCell<Vec<TileCoord>>,
+		released: RefCell<HashSet<TileCoord>>,
+		wakers: RefCell<HashMap<TileCoord, Waker>>,
+	}
+
+	impl GatedSource {
+		fn release(&self, coord: TileCoord) {
+			self.released.borrow_mut().insert(coord);
+			if let Some(waker) = self.wakers.borrow_mut().remove(&coord) {
+				waker.wake();
+			}
+		}
+	}
+
+	#[async_trait(?Send)]
+	impl TileSource for GatedSource {
+		async fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Tile> {
+			let coord = TileCoord::new(x, y, z);
+			self.started.borrow_mut().push(coord);
+			poll_fn(|cx| {
+				if self.released.borrow().contains(&coord) {
+					Poll::Ready(())
+				} else {
+					self.wakers.borrow_mut().insert(coord, cx.waker().clone());
+					Poll::Pending
+				}
+			})
+			.await;
+			Ok(Tile::new())
+		}
+	}
+
+	// Whatever's finished without waiting for anything else
+	fn poll(loader: &mut TileLoader) -> Option<TileCoord> {
+		let waker = noop_waker();
+		match loader.poll_next(&mut Context::from_waker(&waker)) {
+			Poll::Ready((coord, result)) => {
+				assert!(result.is_ok());
+				Some(coord)
+			}
+			Poll::Pending => None,
+		}
+	}
+
+	fn tile(x: i32) -> TileCoord {
+		TileCoord::new(x, 0, 4)
+	}
+
+	fn near(distance: f32) -> Priority {
+		Priority { zoom: 4, distance }
+	}
+
+	#[test]
+	fn repeated_requests_are_ignored() {
+		let source = Rc::new(GatedSource::default());
+		let mut loader = TileLoader::new(source.clone(), 4);
+		assert!(loader.request(tile(0), near(0.0)));
+		assert!(!loader.request(tile(0), near(1.0)));
+		assert_eq!(poll(&mut loader), None);
+
+		// Still in flight
+		assert!(!loader.request(tile(0), near(0.0)));
+		source.release(tile(0));
+		assert_eq!(poll(&mut loader), Some(tile(0)));
+		assert_eq!(poll(&mut loader), None);
+		assert!(loader.is_idle());
+		assert_eq!(*source.started.borrow(), vec![tile(0)]);
+
+		// Done, so it can be asked for again
+		assert!(loader.request(tile(0), near(0.0)));
+	}
+
+	#[test]
+	fn limits_requests_in_flight() {
+		let source = Rc::new(GatedSource::default());
+		let mut loader = TileLoader::new(source.clone(), 2);
+		for x in 0..4 {
+			loader.request(tile(x), near(x as f32));
+		}
+		assert_eq!(poll(&mut loader), None);
+		assert_eq!(loader.in_flight(), 2);
+		assert_eq!(*source.started.borrow(), vec![tile(0), tile(1)]);
+
+		source.release(tile(1));
+		assert_eq!(poll(&mut loader), Some(tile(1)));
+		assert_eq!(poll(&mut loader), None);
+		assert_eq!(loader.in_flight(), 2);
+		assert_eq!(*source.started.borrow(), vec![tile(0), tile(1), tile(2)]);
+
+		for x in 0..4 {
+			source.release(tile(x));
+		}
+		let mut loaded = vec![];
+		while let Some(coord) = poll(&mut loader) {
+			loaded.push(coord);
+		}
+		loaded.sort();
+		assert_eq!(loaded, vec![tile(0), tile(2), tile(3)]);
+		assert!(loader.is_idle());
+	}
+
+	#[test]
+	fn coarser_and_closer_tiles_first() {
+		let source = Rc::new(GatedSource::default());
+		let mut loader = TileLoader::new(source.clone(), 1);
+		let coarse = TileCoord::new(0, 0, 2);
+		loader.request(tile(0), near(5.0));
+		loader.request(tile(1), near(1.0));
+		loader.request(coarse, Priority { zoom: 2, distance: 9.0 });
+		loader.request(tile(2), near(1.0));
+
+		for _ in 0..4 {
+			assert_eq!(poll(&mut loader), None);
+			let started = *source.started.borrow().last().unwrap();
+			source.release(started);
+			assert_eq!(poll(&mut loader), Some(started));
+		}
+		// Ties go to whichever was asked for first
+		assert_eq!(*source.started.borrow(), vec![coarse, tile(1), tile(2), tile(0)]);
+	}
+
+	#[test]
+	fn reprioritising() {
+		let source = Rc::new(GatedSource::default());
+		let mut loader = TileLoader::new(source.clone(), 1);
+		for x in 0..4 {
+			loader.request(tile(x), near(x as f32));
+		}
+		assert_eq!(poll(&mut loader), None);
+
+		// The view moved: 3 is now closest, and 0 (in flight) and 2 (queued) are out of sight
+		loader.reprioritise(|coord| match coord.x {
+			0 | 2 => None,
+			x => Some(near(-x as f32)),
+		});
+		assert!(!loader.is_pending(&tile(0)));
+		assert!(!loader.is_pending(&tile(2)));
+		assert_eq!(loader.in_flight(), 0);
+
+		// The cancelled fetch never comes back, even once it's finished
+		source.release(tile(0));
+		assert_eq!(poll(&mut loader), None);
+		source.release(tile(3));
+		assert_eq!(poll(&mut loader), Some(tile(3)));
+		source.release(tile(1));
+		assert_eq!(poll(&mut loader), Some(tile(1)));
+		assert!(loader.is_idle());
+		assert_eq!(*source.started.borrow(), vec![tile(0), tile(3), tile(1)]);
+	}
+
+	#[test]
+	fn cancelling() {
+		let source = Rc::new(GatedSource::default());
+		let mut loader = TileLoader::new(source.clone(), 1);
+		loader.request(tile(0), near(0.0));
+		loader.request(tile(1), near(1.0));
+		assert_eq!(poll(&mut loader), None);
+
+		assert!(loader.cancel(&tile(1)));
+		assert!(loader.cancel(&tile(0)));
+		assert!(!loader.cancel(&tile(0)));
+		assert!(loader.is_idle());
+
+		source.release(tile(0));
+		assert_eq!(poll(&mut loader), None);
+		assert_eq!(*source.started.borrow(), vec![tile(0)]);
+	}
+}
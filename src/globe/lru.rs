@@ -0,0 +1,209 @@
+use crate::mercator::TileCoord;
+use crate::tile::Tile;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug)]
+struct Entry {
+	tile: Tile,
+	size: usize,
+	last_used: u64,
+}
+
+/// Loaded tiles, evicting the least recently used ones once their meshes take up more than the memory budget
+#[derive(Debug)]
+pub struct TileLru {
+	entries: HashMap<TileCoord, Entry>,
+	/// The same tiles by when they were last used, oldest first
+	order: BTreeMap<u64, TileCoord>,
+	clock: u64,
+	used: usize,
+	budget: usize,
+}
+
+impl TileLru {
+	pub fn new(budget: usize) -> Self {
+		Self {
+			entries: HashMap::new(),
+			order: BTreeMap::new(),
+			clock: 0,
+			used: 0,
+			budget,
+		}
+	}
+
+	pub fn budget(&self) -> usize {
+		self.budget
+	}
+
+	/// Bytes used by the cached tiles' meshes
+	pub fn used(&self) -> usize {
+		self.used
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn contains(&self, coord: &TileCoord) -> bool {
+		self.entries.contains_key(coord)
+	}
+
+	/// Look up a tile without counting it as used
+	pub fn peek(&self, coord: &TileCoord) -> Option<&Tile> {
+		self.entries.get(coord).map(|e| &e.tile)
+	}
+
+	/// Look up a tile and mark it as the most recently used
+	pub fn get(&mut self, coord: &TileCoord) -> Option<&Tile> {
+		self.clock += 1;
+		let clock = self.clock;
+		let entry = self.entries.get_mut(coord)?;
+		self.order.remove(&entry.last_used);
+		self.order.insert(clock, *coord);
+		entry.last_used = clock;
+		Some(&entry.tile)
+	}
+
+	/// Mark a tile as the most recently used
+	pub fn touch(&mut self, coord: &TileCoord) {
+		self.get(coord);
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&TileCoord, &Tile)> {
+		self.entries.iter().map(|(coord, e)| (coord, &e.tile))
+	}
+
+	/// Insert a tile, returning the coordinates of any tiles evicted to make room for it
+	pub fn insert(&mut self, coord: TileCoord, tile: Tile) -> Vec<TileCoord> {
+		self.clock += 1;
+		let size = tile.byte_size();
+		let entry = Entry {
+			tile,
+			size,
+			last_used: self.clock,
+		};
+		if let Some(old) = self.entries.insert(coord, entry) {
+			self.used -= old.size;
+			self.order.remove(&old.last_used);
+		}
+		self.order.insert(self.clock, coord);
+		self.used += size;
+		self.evict(Some(coord))
+	}
+
+	pub fn remove(&mut self, coord: &TileCoord) -> Option<Tile> {
+		self.entries.remove(coord).map(|e| {
+			self.used -= e.size;
+			self.order.remove(&e.last_used);
+			e.tile
+		})
+	}
+
+	/// Change the budget, returning the coordinates of any tiles that no longer fit
+	pub fn set_budget(&mut self, budget: usize) -> Vec<TileCoord> {
+		self.budget = budget;
+		self.evict(None)
+	}
+
+	// Drop the oldest tiles until we're within budget, never dropping `keep`
+	fn evict(&mut self, keep: Option<TileCoord>) -> Vec<TileCoord> {
+		let mut evicted = vec![];
+		while self.used > self.budget {
+			let oldest = self.order.values().find(|coord| Some(**coord) != keep).copied();
+			match oldest {
+				Some(coord) => {
+					self.remove(&coord);
+					evicted.push(coord);
+				}
+				None => break,
+			}
+		}
+		evicted
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mesh::Texture;
+
+	// Raster tiles all have the same mesh, so their sizes only differ by the image
+	fn tile(pixels: usize) -> Tile {
+		let texture = Texture {
+			width: pixels as u32,
+			height: 1,
+			pixels: vec![0; pixels * 4],
+		};
+		Tile::from_raster(texture, 0, 0, 0)
+	}
+
+	fn coord(x: i32) -> TileCoord {
+		TileCoord::new(x, 0, 4)
+	}
+
+	#[test]
+	fn evicts_the_least_recently_used() {
+		let size = tile(1).byte_size();
+		let mut lru = TileLru::new(size * 3);
+		for x in 0..3 {
+			assert!(lru.insert(coord(x), tile(1)).is_empty());
+		}
+		assert_eq!(lru.used(), size * 3);
+
+		// 1 is now the oldest
+		lru.touch(&coord(0));
+		assert!(lru.get(&coord(2)).is_some());
+		assert_eq!(lru.insert(coord(3), tile(1)), vec![coord(1)]);
+		assert_eq!(lru.insert(coord(4), tile(1)), vec![coord(0)]);
+		// Peeking doesn't count as using
+		lru.peek(&coord(2));
+		assert_eq!(lru.insert(coord(5), tile(1)), vec![coord(2)]);
+
+		assert_eq!(lru.len(), 3);
+		assert_eq!(lru.used(), size * 3);
+		assert!(!lru.contains(&coord(2)));
+	}
+
+	#[test]
+	fn replacing_a_tile_updates_its_size() {
+		let small = tile(1).byte_size();
+		let big = tile(100).byte_size();
+		let mut lru = TileLru::new(small * 2 + big);
+		lru.insert(coord(0), tile(1));
+		lru.insert(coord(0), tile(100));
+		assert_eq!(lru.used(), big);
+		assert_eq!(lru.len(), 1);
+		lru.remove(&coord(0));
+		assert_eq!(lru.used(), 0);
+		assert!(lru.is_empty());
+	}
+
+	#[test]
+	fn keeps_the_new_tile_even_if_it_is_too_big() {
+		let mut lru = TileLru::new(tile(1).byte_size() * 2);
+		lru.insert(coord(0), tile(1));
+		lru.insert(coord(1), tile(1));
+		assert_eq!(lru.insert(coord(2), tile(100_000)), vec![coord(0), coord(1)]);
+		assert!(lru.contains(&coord(2)));
+		assert!(lru.used() > lru.budget());
+	}
+
+	#[test]
+	fn shrinking_the_budget() {
+		let size = tile(1).byte_size();
+		let mut lru = TileLru::new(size * 4);
+		for x in 0..4 {
+			lru.insert(coord(x), tile(1));
+		}
+		lru.touch(&coord(0));
+		assert!(lru.set_budget(size * 4).is_empty());
+		assert_eq!(lru.set_budget(size * 2), vec![coord(1), coord(2)]);
+		assert_eq!(lru.set_budget(0), vec![coord(3), coord(0)]);
+		assert!(lru.is_empty());
+		assert_eq!(lru.used(), 0);
+	}
+}
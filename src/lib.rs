@@ -1,12 +1,27 @@
 pub mod camera;
 pub mod data;
+pub mod error;
 pub mod geometry;
 pub mod globe;
+pub mod mercator;
 pub mod mesh;
 pub mod protos;
+pub mod terrain;
+pub mod tessellate;
 pub mod tile;
 pub mod scene;
 pub mod input;
 
+pub use error::{Error, Result};
+
 #[cfg(target_arch = "wasm32")]
 pub mod wasm;
+
+/// Log to the browser console, or stderr when running natively
+#[cfg(target_arch = "wasm32")]
+pub use wasm::log;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(s: &str) {
+	eprintln!("{}", s);
+}
@@ -0,0 +1,345 @@
+use crate::error::{Error, Result};
+use crate::geometry::LonLat;
+use nalgebra as na;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Furthest north or south web mercator goes, in degrees, where the map is as tall as it is wide
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Deepest zoom level whose tiles can be numbered with an `i32`
+pub const MAX_ZOOM: i32 = 30;
+
+/// Position in web mercator tile space to lon/lat, e.g. (0.5, 0.5) at zoom 0 is the middle of the map
+pub fn tile_to_lonlat(x: f64, y: f64, zoom: i32) -> LonLat {
+	let n = 2.0f64.powi(zoom);
+	let lon = x / n * 360.0 - 180.0;
+	let lat = (PI * (1.0 - 2.0 * y / n)).sinh().atan().to_degrees();
+	LonLat::new(lon as f32, lat as f32)
+}
+
+/// Lon/lat to a position in web mercator tile space, the integer part is the tile containing it
+///
+/// Longitudes aren't wrapped, so points past the antimeridian land on tiles off either side of the map.
+pub fn lonlat_to_tile(ll: &LonLat, zoom: i32) -> (f64, f64) {
+	let n = 2.0f64.powi(zoom);
+	let lat = (ll.lat() as f64).clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+	let x = (ll.lon() as f64 + 180.0) / 360.0 * n;
+	let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n;
+	(x, y)
+}
+
+/// A tile in the web mercator pyramid, `x` counts east from the antimeridian and `y` south from the top of the map
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileCoord {
+	pub x: i32,
+	pub y: i32,
+	pub z: i32,
+}
+
+impl TileCoord {
+	pub fn new(x: i32, y: i32, z: i32) -> Self {
+		Self { x, y, z }
+	}
+
+	/// The tile containing a point, latitudes past the edge of the map land on its top or bottom row
+	pub fn from_lonlat(ll: &LonLat, z: i32) -> Self {
+		let (x, y) = lonlat_to_tile(ll, z);
+		let last = (1i64 << z) as f64 - 1.0;
+		Self::new(x.floor().clamp(0.0, last) as i32, y.floor().clamp(0.0, last) as i32, z)
+	}
+
+	/// Number of tiles across the map at this zoom level
+	pub fn tiles_across(&self) -> i64 {
+		1i64 << self.z.clamp(0, MAX_ZOOM)
+	}
+
+	/// Whether the tile is on the map, i.e. the zoom is in 0..=`MAX_ZOOM` and `x` and `y` are in 0..2^z
+	pub fn is_valid(&self) -> bool {
+		let n = self.tiles_across();
+		(0..=MAX_ZOOM).contains(&self.z) && (0..n).contains(&(self.x as i64)) && (0..n).contains(&(self.y as i64))
+	}
+
+	/// The same tile with `x` wrapped round the antimeridian, `y` is left alone as the map doesn't wrap at the poles
+	pub fn wrapped(&self) -> Self {
+		Self::new((self.x as i64).rem_euclid(self.tiles_across()) as i32, self.y, self.z)
+	}
+
+	/// The tile one level up containing this one, `None` at zoom 0
+	pub fn parent(&self) -> Option<Self> {
+		if self.z > 0 {
+			Some(self.ancestor(self.z - 1))
+		} else {
+			None
+		}
+	}
+
+	/// The tile at zoom `z` containing this one, a `z` deeper than the tile gives the tile itself
+	pub fn ancestor(&self, z: i32) -> Self {
+		if z >= self.z {
+			return *self;
+		}
+		let z = z.max(0);
+		let dz = (self.z - z).min(63);
+		Self::new((self.x as i64 >> dz) as i32, (self.y as i64 >> dz) as i32, z)
+	}
+
+	/// The four tiles one level down, in quadkey order: north west, north east, south west, south east
+	pub fn children(&self) -> [Self; 4] {
+		let (x, y, z) = (self.x * 2, self.y * 2, self.z + 1);
+		[
+			Self::new(x, y, z),
+			Self::new(x + 1, y, z),
+			Self::new(x, y + 1, z),
+			Self::new(x + 1, y + 1, z),
+		]
+	}
+
+	/// The tiles around this one, wrapping round the antimeridian
+	///
+	/// There's nothing past the poles, so tiles on the top and bottom rows have fewer neighbours, and at zoom 0 and
+	/// 1 the same tile can come up more than once when wrapping brings it back round.
+	pub fn neighbours(&self) -> Vec<Self> {
+		let n = self.tiles_across();
+		let mut tiles = Vec::with_capacity(8);
+		for dy in -1..=1 {
+			let y = self.y as i64 + dy;
+			if y < 0 || y >= n {
+				continue;
+			}
+			for dx in -1..=1 {
+				if dx != 0 || dy != 0 {
+					tiles.push(Self::new(self.x + dx, y as i32, self.z).wrapped());
+				}
+			}
+		}
+		tiles
+	}
+
+	/// Bing style quadkey, one base 4 digit per zoom level, zoom 0 is the empty string
+	pub fn quadkey(&self) -> String {
+		(1..=self.z)
+			.rev()
+			.map(|i| {
+				let mask = 1 << (i - 1);
+				let mut digit = 0;
+				if self.x & mask != 0 {
+					digit += 1;
+				}
+				if self.y & mask != 0 {
+					digit += 2;
+				}
+				std::char::from_digit(digit, 4).unwrap()
+			})
+			.collect()
+	}
+
+	/// The tile a Bing style quadkey refers to, see `quadkey`
+	pub fn from_quadkey(key: &str) -> Result<Self> {
+		if key.len() > MAX_ZOOM as usize {
+			return Err(Error::QuadKey(format!("{} is deeper than zoom {}", key, MAX_ZOOM)));
+		}
+		let mut tile = Self::default();
+		for c in key.chars() {
+			let digit = c
+				.to_digit(4)
+				.ok_or_else(|| Error::QuadKey(format!("{} has a digit that isn't 0 to 3", key)))?;
+			tile = tile.children()[digit as usize];
+		}
+		Ok(tile)
+	}
+
+	/// South west and north east corners
+	pub fn bounds(&self) -> (LonLat, LonLat) {
+		let (x, y) = (self.x as f64, self.y as f64);
+		(tile_to_lonlat(x, y + 1.0, self.z), tile_to_lonlat(x + 1.0, y, self.z))
+	}
+
+	/// The middle of the tile in web mercator, which is a little north of halfway between its edges
+	pub fn center(&self) -> LonLat {
+		tile_to_lonlat(self.x as f64 + 0.5, self.y as f64 + 0.5, self.z)
+	}
+
+	/// Lon/lat of a point given in the tile's own coordinates, `extent` units across from the top left corner
+	///
+	/// An extent of 1 is the unit square meshes are built in, a vector tile layer uses its own `extent`.
+	pub fn pixel_to_lonlat(&self, p: &na::Point2<f32>, extent: u32) -> LonLat {
+		let extent = extent as f64;
+		tile_to_lonlat(
+			self.x as f64 + p.x as f64 / extent,
+			self.y as f64 + p.y as f64 / extent,
+			self.z,
+		)
+	}
+
+	/// Where a lon/lat is in the tile's own coordinates, `extent` units across from the top left corner
+	///
+	/// Points off the tile come out below 0 or past `extent`, see `pixel_to_lonlat`.
+	pub fn lonlat_to_pixel(&self, ll: &LonLat, extent: u32) -> na::Point2<f32> {
+		let (x, y) = lonlat_to_tile(ll, self.z);
+		let extent = extent as f64;
+		na::Point2::new(
+			((x - self.x as f64) * extent) as f32,
+			((y - self.y as f64) * extent) as f32,
+		)
+	}
+}
+
+impl fmt::Display for TileCoord {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}/{}/{}", self.z, self.x, self.y)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_near(a: f32, b: f32, epsilon: f32) {
+		assert!((a - b).abs() <= epsilon, "{} isn't within {} of {}", a, epsilon, b);
+	}
+
+	#[test]
+	fn quadkeys() {
+		assert_eq!(TileCoord::new(3, 5, 3).quadkey(), "213");
+		assert_eq!(TileCoord::new(0, 0, 0).quadkey(), "");
+		assert_eq!(TileCoord::new(35210, 21493, 16).quadkey(), "1202102332221212");
+
+		assert_eq!(TileCoord::from_quadkey("213").unwrap(), TileCoord::new(3, 5, 3));
+		assert_eq!(TileCoord::from_quadkey("").unwrap(), TileCoord::new(0, 0, 0));
+		assert_eq!(TileCoord::from_quadkey("1202102332221212").unwrap(), TileCoord::new(35210, 21493, 16));
+		assert!(TileCoord::from_quadkey("214").is_err());
+		assert!(TileCoord::from_quadkey("0123x").is_err());
+		assert!(TileCoord::from_quadkey(&"0".repeat(31)).is_err());
+	}
+
+	#[test]
+	fn validity() {
+		assert!(TileCoord::new(0, 0, 0).is_valid());
+		assert!(TileCoord::new(3, 3, 2).is_valid());
+		assert!(TileCoord::new((1 << 30) - 1, 0, 30).is_valid());
+		assert!(!TileCoord::new(4, 0, 2).is_valid());
+		assert!(!TileCoord::new(-1, 0, 2).is_valid());
+		assert!(!TileCoord::new(0, 4, 2).is_valid());
+		assert!(!TileCoord::new(0, 0, -1).is_valid());
+		assert!(!TileCoord::new(0, 0, 31).is_valid());
+	}
+
+	#[test]
+	fn wrapping() {
+		assert_eq!(TileCoord::new(-1, 0, 2).wrapped(), TileCoord::new(3, 0, 2));
+		assert_eq!(TileCoord::new(4, 1, 2).wrapped(), TileCoord::new(0, 1, 2));
+		assert_eq!(TileCoord::new(-9, 1, 2).wrapped(), TileCoord::new(3, 1, 2));
+		assert_eq!(TileCoord::new(2, 7, 2).wrapped(), TileCoord::new(2, 7, 2));
+	}
+
+	#[test]
+	fn hierarchy() {
+		let tile = TileCoord::new(3, 5, 3);
+		assert_eq!(tile.parent(), Some(TileCoord::new(1, 2, 2)));
+		assert_eq!(tile.ancestor(1), TileCoord::new(0, 1, 1));
+		assert_eq!(tile.ancestor(3), tile);
+		assert_eq!(tile.ancestor(5), tile);
+		assert_eq!(tile.ancestor(-1), TileCoord::new(0, 0, 0));
+		assert_eq!(TileCoord::new(-1, 0, 40).ancestor(0), TileCoord::new(-1, 0, 0));
+		assert_eq!(TileCoord::new(0, 0, 0).parent(), None);
+
+		let children = tile.children();
+		assert_eq!(
+			children,
+			[
+				TileCoord::new(6, 10, 4),
+				TileCoord::new(7, 10, 4),
+				TileCoord::new(6, 11, 4),
+				TileCoord::new(7, 11, 4),
+			]
+		);
+		for (i, child) in children.iter().enumerate() {
+			assert_eq!(child.parent(), Some(tile));
+			assert_eq!(child.quadkey(), format!("213{}", i));
+		}
+	}
+
+	#[test]
+	fn neighbours() {
+		let mut tiles = TileCoord::new(0, 1, 2).neighbours();
+		tiles.sort();
+		let mut expected = vec![
+			TileCoord::new(3, 0, 2),
+			TileCoord::new(0, 0, 2),
+			TileCoord::new(1, 0, 2),
+			TileCoord::new(3, 1, 2),
+			TileCoord::new(1, 1, 2),
+			TileCoord::new(3, 2, 2),
+			TileCoord::new(0, 2, 2),
+			TileCoord::new(1, 2, 2),
+		];
+		expected.sort();
+		assert_eq!(tiles, expected);
+
+		// Nothing north of the top row
+		let tiles = TileCoord::new(2, 0, 2).neighbours();
+		assert_eq!(tiles.len(), 5);
+		assert!(tiles.iter().all(|t| t.y >= 0 && t.is_valid()));
+	}
+
+	#[test]
+	fn bounds() {
+		let (sw, ne) = TileCoord::new(0, 0, 0).bounds();
+		assert_near(sw.lon(), -180.0, 1e-4);
+		assert_near(sw.lat(), -85.051_13, 1e-4);
+		assert_near(ne.lon(), 180.0, 1e-4);
+		assert_near(ne.lat(), 85.051_13, 1e-4);
+
+		let (sw, ne) = TileCoord::new(1, 1, 1).bounds();
+		assert_near(sw.lon(), 0.0, 1e-4);
+		assert_near(sw.lat(), -85.051_13, 1e-4);
+		assert_near(ne.lon(), 180.0, 1e-4);
+		assert_near(ne.lat(), 0.0, 1e-4);
+
+		let (sw, ne) = TileCoord::new(511, 340, 10).bounds();
+		assert_near(sw.lon(), -0.351_562_5, 1e-5);
+		assert_near(sw.lat(), 51.399_206, 1e-4);
+		assert_near(ne.lon(), 0.0, 1e-5);
+		assert_near(ne.lat(), 51.618_017, 1e-4);
+
+		let center = TileCoord::new(0, 0, 0).center();
+		assert_near(center.lon(), 0.0, 1e-4);
+		assert_near(center.lat(), 0.0, 1e-4);
+	}
+
+	#[test]
+	fn tile_containing() {
+		let london = LonLat::new(-0.1278, 51.5074);
+		assert_eq!(TileCoord::from_lonlat(&london, 10), TileCoord::new(511, 340, 10));
+		let wellington = LonLat::new(174.7762, -41.2865);
+		assert_eq!(TileCoord::from_lonlat(&wellington, 12), TileCoord::new(4036, 2564, 12));
+
+		// The poles and antimeridian end up on the map's edge tiles
+		assert_eq!(TileCoord::from_lonlat(&LonLat::new(180.0, 90.0), 3), TileCoord::new(7, 0, 3));
+		assert_eq!(TileCoord::from_lonlat(&LonLat::new(-180.0, -90.0), 3), TileCoord::new(0, 7, 3));
+	}
+
+	#[test]
+	fn pixels() {
+		let tile = TileCoord::new(511, 340, 10);
+		let p = tile.lonlat_to_pixel(&LonLat::new(-0.1278, 51.5074), 4096);
+		assert_near(p.x, 2607.02, 0.05);
+		assert_near(p.y, 2073.13, 0.05);
+
+		// The middle of a tile is halfway across it however many units it's split into
+		for &extent in &[1, 256, 4096] {
+			let p = tile.lonlat_to_pixel(&tile.center(), extent);
+			assert_near(p.x, extent as f32 / 2.0, extent as f32 * 1e-4);
+			assert_near(p.y, extent as f32 / 2.0, extent as f32 * 1e-4);
+		}
+
+		let corner = tile.pixel_to_lonlat(&na::Point2::new(0.0, 4096.0), 4096);
+		assert_eq!(corner, tile.bounds().0);
+
+		let ll = tile.pixel_to_lonlat(&na::Point2::new(1000.0, 3000.0), 4096);
+		let p = tile.lonlat_to_pixel(&ll, 4096);
+		assert_near(p.x, 1000.0, 0.1);
+		assert_near(p.y, 3000.0, 0.1);
+	}
+}
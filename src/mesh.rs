@@ -1,19 +1,112 @@
 use nalgebra as na;
+use std::fmt;
+use std::rc::Rc;
+
+/// An RGBA image, 4 bytes per pixel, row by row from the top
+#[derive(Clone, Default)]
+pub struct Texture {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<u8>,
+}
+
+impl fmt::Debug for Texture {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Texture")
+			.field("width", &self.width)
+			.field("height", &self.height)
+			.finish()
+	}
+}
+
+/// How a line vertex is pushed out from the middle of the line when it's drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Extrusion {
+	/// Which way and how far to push, along the surface of the globe and in CSS pixels
+	pub offset: na::Vector3<f32>,
+	/// Half the width of the line in CSS pixels
+	pub half_width: f32,
+	/// Across the line from -1.0 on one side to 1.0 on the other, for smoothing the edges
+	pub edge: f32,
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct Mesh {
 	pub vertices: Vec<na::Point3<f32>>,
 	pub triangles: Vec<(usize, usize, usize)>,
+	/// Texture coordinates for each vertex, empty unless the mesh is textured
+	pub uvs: Vec<na::Point2<f32>>,
+	pub texture: Option<Rc<Texture>>,
+	/// Extrusions for each vertex, empty unless the mesh has lines in it
+	pub extrusions: Vec<Extrusion>,
 }
 
 impl Mesh {
 	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// A mesh with an image stretched over it, `uvs` has to line up with `vertices`
+	pub fn textured(
+		vertices: Vec<na::Point3<f32>>,
+		uvs: Vec<na::Point2<f32>>,
+		triangles: Vec<(usize, usize, usize)>,
+		texture: Rc<Texture>,
+	) -> Self {
 		Self {
-			vertices: vec![],
-			triangles: vec![],
+			vertices,
+			triangles,
+			uvs,
+			texture: Some(texture),
+			extrusions: vec![],
+		}
+	}
+
+	/// A mesh of lines that are widened as they're drawn, `extrusions` has to line up with `vertices`
+	pub fn lines(
+		vertices: Vec<na::Point3<f32>>,
+		extrusions: Vec<Extrusion>,
+		triangles: Vec<(usize, usize, usize)>,
+	) -> Self {
+		Self {
+			vertices,
+			triangles,
+			extrusions,
+			..Self::default()
 		}
 	}
 
+	pub fn is_textured(&self) -> bool {
+		self.texture.is_some()
+	}
+
+	pub fn has_lines(&self) -> bool {
+		!self.extrusions.is_empty()
+	}
+
+	/// Add another mesh's triangles to this one, keeping this mesh's texture
+	///
+	/// Vertices from an untextured mesh get negative texture coordinates, which are drawn in plain colour. Vertices
+	/// that aren't part of a line get no extrusion.
+	pub fn append(&mut self, other: Mesh) {
+		let offset = self.vertices.len();
+		if self.has_lines() || other.has_lines() {
+			self.extrusions.resize(offset, Extrusion::default());
+			self.extrusions.extend(&other.extrusions);
+			self.extrusions.resize(offset + other.vertices.len(), Extrusion::default());
+		}
+		if self.is_textured() {
+			let untextured = na::Point2::new(-1.0, -1.0);
+			if other.is_textured() {
+				self.uvs.extend(other.uvs);
+			}
+			self.uvs.resize(offset + other.vertices.len(), untextured);
+		}
+		self.vertices.extend(other.vertices);
+		self.triangles
+			.extend(other.triangles.into_iter().map(|(a, b, c)| (a + offset, b + offset, c + offset)));
+	}
+
 	pub fn cube(s: f32) -> Self {
 		let vertices = vec![
 			// Front
@@ -51,21 +144,43 @@ impl Mesh {
 		Self {
 			vertices,
 			triangles,
+			..Self::default()
 		}
 	}
 
 	pub fn vertices_as_vec(&self) -> Vec<f32> {
-		self.vertices.iter().map(|v| v.iter()).flatten().map(|f| *f).collect()
+		self.vertices.iter().flat_map(|v| v.iter()).copied().collect()
 	}
 
 	pub fn triangles_as_vec(&self) -> Vec<u32> {
 		self.triangles
 			.iter()
-			.map(|v| vec![v.0 as u32, v.1 as u32, v.2 as u32])
-			.flatten()
+			.flat_map(|v| vec![v.0 as u32, v.1 as u32, v.2 as u32])
 			.collect()
 	}
 
+	pub fn uvs_as_vec(&self) -> Vec<f32> {
+		self.uvs.iter().flat_map(|v| v.iter()).copied().collect()
+	}
+
+	/// Extrusion offsets followed by the edge and half width, 5 floats per vertex
+	pub fn extrusions_as_vec(&self) -> Vec<f32> {
+		self.extrusions
+			.iter()
+			.flat_map(|e| vec![e.offset.x, e.offset.y, e.offset.z, e.edge, e.half_width])
+			.collect()
+	}
+
+	/// Approximate memory used by the vertex and index data
+	///
+	/// The texture isn't counted as it can be shared with other meshes, see `Tile::byte_size`.
+	pub fn byte_size(&self) -> usize {
+		self.vertices.len() * std::mem::size_of::<na::Point3<f32>>()
+			+ self.triangles.len() * std::mem::size_of::<(usize, usize, usize)>()
+			+ self.uvs.len() * std::mem::size_of::<na::Point2<f32>>()
+			+ self.extrusions.len() * std::mem::size_of::<Extrusion>()
+	}
+
 	pub fn vertices(&self) -> &Vec<na::Point3<f32>> {
 		&self.vertices
 	}
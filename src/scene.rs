@@ -1,20 +1,17 @@
 use crate::camera::Camera;
-use crate::globe::Globe;
+use crate::globe::{Globe, TileView};
 use crate::input::UserInputs;
+use crate::mercator::TileCoord;
 use crate::mesh::Mesh;
-use crate::tile::Tile;
-use crate::geometry::{point_to_lonlat, lonlat_to_point, pixel_to_lonlat};
+use crate::geometry::{angular_distance, point_to_lonlat, lonlat_to_point, LonLat};
 use nalgebra as na;
 use std::collections::HashMap;
 use std::f32::consts::PI;
 use std::{cell::RefCell, rc::Rc};
-use crate::wasm;
+use crate::log;
 
-type TileCoord = (i32, i32, i32);
-
-fn rad_to_deg(rad: f32) -> f32 {
-	((rad * (180.0 / PI) + 180.0) % 360.0) - 180.0
-}
+// Zoom level tiles are loaded at
+const TILE_ZOOM: i32 = 2;
 
 fn map_range(val: f32, min0: f32, max0: f32, min1: f32, max1: f32) -> f32 {
 	(val - min0) * (max1 - min1) / (max0 - min0) + min1
@@ -64,8 +61,11 @@ pub struct SceneItem {
 #[derive(Debug, Default)]
 pub struct Scene {
 	items: Vec<SceneItem>,
+	free_items: Vec<usize>,
 	markers: HashMap<usize, na::Point2<f32>>,
 	tiles: HashMap<TileCoord, usize>,
+	// Vector tile layers to draw, all of them if `None`
+	layers: Option<Vec<String>>,
 	camera: Camera,
 	globe: Rc<RefCell<Globe>>,
 	globe_rotation: na::Vector3<f32>,
@@ -95,13 +95,10 @@ impl Scene {
 		let y = -(pos.1 as f32 * 2.0 - h) / h;
 
 		// Camera -> world
-		let inv_view = self.camera.view().try_inverse().unwrap();
-		let inv_proj = self.camera.projection().try_inverse().unwrap();
 		let vp = self.camera.view_projection().try_inverse().unwrap();
-		let pv = inv_view * inv_proj;
 
 		// Transform origin/dir from screen to world space
-		let mut origin = vp.transform_point(&na::Point3::new(x, y, -1.0));
+		let origin = vp.transform_point(&na::Point3::new(x, y, -1.0));
 		let dest       = vp.transform_point(&na::Point3::new(x, y, 0.0));
 		let dir = (dest - origin).normalize();
 
@@ -123,25 +120,18 @@ impl Scene {
 	}
 
 	fn screen_to_lonlat(&self, pos: (i32, i32), rotate: bool) -> Option<na::Point2<f32>> {
-		if let Some(p) = self.screen_to_surface(pos, rotate) {
-			Some(point_to_lonlat(&p))
-		}
-		else {
-			None
-		}
+		self.screen_to_surface(pos, rotate).map(|p| point_to_lonlat(&p))
 	}
 
 	fn on_click(&mut self, pos: (i32, i32)) {
 		if let Some(ll) = self.screen_to_lonlat(pos, true) {
-			let model = na::Matrix4::from_euler_angles(0.0, -self.globe_rotation.y, 0.0)
-			* na::Matrix4::from_euler_angles(-self.globe_rotation.x, 0.0, 0.0);
 			self.add_marker(ll);
 		}
 	}
 
 	fn on_mouse_down(&mut self, pos: (i32, i32)) {
 		self.clicking = true;
-		wasm::log(&format!("START {:?} / {:?}", pos, self.globe_rotation));
+		log(&format!("START {:?} / {:?}", pos, self.globe_rotation));
 	}
 
 	fn on_mouse_up(&mut self, pos: (i32, i32)) {
@@ -160,7 +150,7 @@ impl Scene {
 		// 90deg distance
 		let s = 340.0;
 
-		let old_pos = self.prev_mouse_position.as_ref().unwrap().clone();
+		let old_pos = *self.prev_mouse_position.as_ref().unwrap();
 
 		self.clicking = false;
 		// Mouse delta
@@ -194,12 +184,6 @@ impl Scene {
 
 	}
 
-	fn load_tile(&mut self, x: i32, y: i32, z: i32) {
-		if let Ok(mut globe) = self.globe.try_borrow_mut() {
-			globe.queue_tile(x, y, z);
-		}
-	}
-
 	pub fn camera(&self) -> &Camera {
 		&self.camera
 	}
@@ -208,14 +192,30 @@ impl Scene {
 		&mut self.camera
 	}
 
-	pub fn add(&mut self, item: SceneItem) -> usize {
+	pub fn add(&mut self, mut item: SceneItem) -> usize {
+		// Reuse removed slots so item IDs stay stable for the renderer
+		if let Some(id) = self.free_items.pop() {
+			item.version = self.items[id].version + 1;
+			self.items[id] = item;
+			return id;
+		}
 		let id = self.items.len();
 		self.items.push(item);
 		id
 	}
 
+	/// Empty an item's slot. The version is bumped so the renderer drops its copy of the mesh.
+	pub fn remove(&mut self, id: usize) {
+		let version = self.items[id].version + 1;
+		self.items[id] = SceneItem {
+			version,
+			..SceneItem::default()
+		};
+		self.free_items.push(id);
+	}
+
 	pub fn add_marker(&mut self, lonlat: na::Point2<f32>) {
-		wasm::log(&format!("Adding marker {:?}", lonlat));
+		log(&format!("Adding marker {:?}", lonlat));
 		let id = self.add(SceneItem {
 			mesh: Mesh::cube(1.0),
 			transform: na::Matrix4::identity(),
@@ -233,7 +233,7 @@ impl Scene {
 	}
 
 	pub fn build_next_tile(&mut self) {
-		if let Ok(globe) = self.globe.try_borrow() {
+		if let Ok(_globe) = self.globe.try_borrow() {
 		}
 	}
 
@@ -241,28 +241,82 @@ impl Scene {
 		self.globe.clone()
 	}
 
+	/// Only draw these vector tile layers, or every layer if `None`
+	pub fn set_layers(&mut self, layers: Option<Vec<String>>) {
+		self.layers = layers;
+
+		// Tiles are rebuilt with the new layers on the next update
+		let ids: Vec<usize> = self.tiles.drain().map(|(_, id)| id).collect();
+		for id in ids {
+			self.remove(id);
+		}
+	}
+
 	pub fn update_tiles(&mut self) {
-		if let Ok(globe) = self.globe.try_borrow() {
-			for (coord, tile) in globe.tiles() {
-				if !self.tiles.contains_key(&coord) {
-					let idx = self.items.len();
-					self.items.push(SceneItem {
-						mesh: tile.mesh(),
-						transform: na::Matrix4::identity(),
-						version: 0,
-					});
-					self.tiles.insert(coord.clone(), idx);
-				}
+		let globe_rc = self.globe.clone();
+		let mut globe = match globe_rc.try_borrow_mut() {
+			Ok(globe) => globe,
+			Err(_) => return,
+		};
+
+		for coord in globe.take_evicted() {
+			if let Some(idx) = self.tiles.remove(&coord) {
+				self.remove(idx);
+			}
+		}
+
+		for (coord, tile) in globe.tiles() {
+			if !self.tiles.contains_key(coord) {
+				let mesh = match &self.layers {
+					Some(layers) => tile.layers_mesh(&layers.iter().map(String::as_str).collect::<Vec<_>>()),
+					None => tile.mesh(),
+				};
+				let idx = self.add(SceneItem {
+					mesh,
+					transform: na::Matrix4::identity(),
+					version: 0,
+				});
+				self.tiles.insert(*coord, idx);
 			}
 		}
 	}
 
+	// Tell the globe what's on screen so it loads those tiles first
+	fn update_view(&mut self) {
+		let (w, h) = self.camera.size();
+		if w <= 0.0 || h <= 0.0 {
+			return;
+		}
+		let to_lonlat = |pos| self.screen_to_lonlat(pos, true).map(|ll| LonLat::new(ll.x, ll.y));
+		let center = match to_lonlat(((w / 2.0) as i32, (h / 2.0) as i32)) {
+			Some(center) => center,
+			None => return,
+		};
+
+		// We can see as far as the horizon, unless the globe fills the screen
+		let corners = [(0, 0), (w as i32, 0), (0, h as i32), (w as i32, h as i32)];
+		let radius = corners
+			.iter()
+			.try_fold(0.0f32, |radius, &pos| {
+				to_lonlat(pos).map(|ll| radius.max(angular_distance(&center, &ll)))
+			})
+			.unwrap_or_else(|| (self.scale() / self.camera.position.coords.norm()).min(1.0).acos().to_degrees());
+
+		if let Ok(mut globe) = self.globe.try_borrow_mut() {
+			globe.set_view(TileView {
+				center,
+				radius,
+				zoom: TILE_ZOOM,
+			});
+		}
+	}
+
 	pub fn scale(&self) -> f32 {
 		map_range(self.zoom.powf(2.0), 0.0, 1.0, 0.5, 1.9)
 	}
 
-	pub fn tick(&mut self, dt: f64, inputs: &UserInputs) {
-		if self.items.len() == 0 {
+	pub fn tick(&mut self, _dt: f64, inputs: &dyn UserInputs) {
+		if self.items.is_empty() {
 			for _ in 0..3 {
 				self.add(SceneItem {
 					mesh: Mesh::cube(1.0),
@@ -275,15 +329,10 @@ impl Scene {
 		self.update_tiles();
 
 		// Update mousewheel zooming
-		let dy = -(inputs.wheel_position().1 - self.prev_wheel_position.as_ref().unwrap().1) as f32;
+		let dy = -(inputs.wheel_position().1 - self.prev_wheel_position.as_ref().unwrap().1);
 		if dy != 0.0 {
 			self.zoom += dy * 0.03;
-			if self.zoom > 1.0 {
-				self.zoom = 1.0;
-			}
-			if self.zoom < 0.0 {
-				self.zoom = 0.0;
-			}
+			self.zoom = self.zoom.clamp(0.0, 1.0);
 		}
 		self.prev_wheel_position = Some(inputs.wheel_position());
 
@@ -317,16 +366,17 @@ impl Scene {
 
 		// Update camera
 		self.camera.position = na::Point3::new(0.0, 0.0, -2.0);
+		self.update_view();
 
 
-		for (coord, item_id) in &mut self.tiles {
+		for item_id in self.tiles.values() {
 			let item = &mut self.items[*item_id];
 			item.transform = model;
 		}
 
 		// Update the markers
 		for (item_id, lonlat) in &self.markers {
-			let pos = lonlat_to_point(&lonlat);
+			let pos = lonlat_to_point(lonlat, 1.0);
 			let item = &mut self.items[*item_id];
 			item.transform = model * na::Matrix4::new_translation(&pos.coords) * na::Matrix4::new_scaling(0.01);
 		}
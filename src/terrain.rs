@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+use crate::mesh::Texture;
+use nalgebra as na;
+use std::f32::consts::PI;
+use std::fmt;
+
+/// Mean radius of the Earth in metres, the globe is drawn with a radius of 1.0
+pub const EARTH_RADIUS: f32 = 6_371_008.8;
+
+/// How elevation is packed into the colour channels of a terrain tile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerrainEncoding {
+	/// Mapbox Terrain-RGB, `-10000 + (r * 65536 + g * 256 + b) * 0.1`
+	TerrainRgb,
+	/// Terrarium, as served by AWS open data, `r * 256 + g + b / 256 - 32768`
+	Terrarium,
+}
+
+impl TerrainEncoding {
+	/// Elevation in metres of a single pixel
+	pub fn height(self, r: u8, g: u8, b: u8) -> f32 {
+		let (r, g, b) = (r as u32, g as u32, b as u32);
+		match self {
+			Self::TerrainRgb => -10_000.0 + ((r << 16) + (g << 8) + b) as f32 * 0.1,
+			Self::Terrarium => ((r << 8) + g) as f32 + b as f32 / 256.0 - 32_768.0,
+		}
+	}
+}
+
+/// A grid of elevations in metres, row by row from the top
+#[derive(Clone, Default)]
+pub struct HeightMap {
+	pub width: u32,
+	pub height: u32,
+	pub heights: Vec<f32>,
+}
+
+impl fmt::Debug for HeightMap {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("HeightMap")
+			.field("width", &self.width)
+			.field("height", &self.height)
+			.finish()
+	}
+}
+
+impl HeightMap {
+	/// Read the elevations out of a decoded terrain image, which can't be empty
+	pub fn decode(texture: &Texture, encoding: TerrainEncoding) -> Result<Self> {
+		if texture.width == 0 || texture.height == 0 {
+			return Err(Error::Image("Terrain image has no pixels".into()));
+		}
+		if texture.pixels.len() != (texture.width * texture.height * 4) as usize {
+			return Err(Error::Image("Terrain image is the wrong size".into()));
+		}
+
+		let heights = texture
+			.pixels
+			.chunks_exact(4)
+			.map(|px| encoding.height(px[0], px[1], px[2]))
+			.collect();
+		Ok(Self {
+			width: texture.width,
+			height: texture.height,
+			heights,
+		})
+	}
+
+	// Only called on height maps with at least one pixel
+	fn get(&self, col: u32, row: u32) -> f32 {
+		let col = col.min(self.width - 1);
+		let row = row.min(self.height - 1);
+		self.heights[(row * self.width + col) as usize]
+	}
+
+	/// Elevation at a point, 0.0 to 1.0 across and down the grid, interpolated between pixel centres
+	pub fn sample(&self, u: f32, v: f32) -> f32 {
+		if self.heights.is_empty() || self.width == 0 || self.height == 0 {
+			return 0.0;
+		}
+
+		let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+		let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+		let (col, row) = (x.floor() as u32, y.floor() as u32);
+		let (fx, fy) = (x.fract(), y.fract());
+
+		let top = self.get(col, row) * (1.0 - fx) + self.get(col + 1, row) * fx;
+		let bottom = self.get(col, row + 1) * (1.0 - fx) + self.get(col + 1, row + 1) * fx;
+		top * (1.0 - fy) + bottom * fy
+	}
+
+	/// Grey shaded relief lit from the north west, so the shape of the land shows up without any imagery
+	///
+	/// `cell_size` is the width of a pixel in metres.
+	pub fn hillshade(&self, cell_size: f32, exaggeration: f32) -> Texture {
+		if self.heights.is_empty() {
+			return Texture::default();
+		}
+
+		let light = na::Vector3::new(-1.0, -1.0, 1.0).normalize();
+		let mut pixels = Vec::with_capacity(self.heights.len() * 4);
+
+		for row in 0..self.height {
+			for col in 0..self.width {
+				// Central differences, rows go south so a positive slope in y faces north
+				let dx = self.get(col + 1, row) - self.get(col.saturating_sub(1), row);
+				let dy = self.get(col, row + 1) - self.get(col, row.saturating_sub(1));
+				let scale = exaggeration / (2.0 * cell_size);
+				let normal = na::Vector3::new(-dx * scale, -dy * scale, 1.0).normalize();
+
+				let shade = (60.0 + 195.0 * normal.dot(&light).max(0.0)) as u8;
+				pixels.extend_from_slice(&[shade, shade, shade, 255]);
+			}
+		}
+
+		Texture {
+			width: self.width,
+			height: self.height,
+			pixels,
+		}
+	}
+}
+
+/// Width in metres of a pixel of a `width` pixel wide tile at a latitude in degrees
+pub fn cell_size(lat: f32, zoom: i32, width: u32) -> f32 {
+	2.0 * PI * EARTH_RADIUS * lat.to_radians().cos() / (2.0f32.powi(zoom) * width as f32)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn flat(width: u32, height: u32, elevation: f32) -> HeightMap {
+		HeightMap {
+			width,
+			height,
+			heights: vec![elevation; (width * height) as usize],
+		}
+	}
+
+	#[test]
+	fn encodings() {
+		assert_eq!(TerrainEncoding::TerrainRgb.height(1, 134, 160), 0.0);
+		assert_eq!(TerrainEncoding::TerrainRgb.height(0, 0, 0), -10_000.0);
+		assert_eq!(TerrainEncoding::Terrarium.height(128, 0, 0), 0.0);
+		assert_eq!(TerrainEncoding::Terrarium.height(128, 1, 128), 1.5);
+	}
+
+	#[test]
+	fn decoding() {
+		let texture = Texture {
+			width: 2,
+			height: 1,
+			pixels: vec![1, 134, 160, 255, 1, 134, 170, 255],
+		};
+		let heights = HeightMap::decode(&texture, TerrainEncoding::TerrainRgb).unwrap();
+		assert_eq!((heights.width, heights.height), (2, 1));
+		assert_eq!(heights.heights, vec![0.0, 1.0]);
+		assert_eq!(heights.sample(0.0, 0.5), 0.0);
+		assert_eq!(heights.sample(0.5, 0.5), 0.5);
+		assert_eq!(heights.sample(1.0, 0.5), 1.0);
+
+		let empty = Texture::default();
+		assert!(HeightMap::decode(&empty, TerrainEncoding::TerrainRgb).is_err());
+		let short = Texture {
+			width: 2,
+			height: 2,
+			pixels: vec![0; 4],
+		};
+		assert!(HeightMap::decode(&short, TerrainEncoding::Terrarium).is_err());
+		assert_eq!(HeightMap::default().sample(0.5, 0.5), 0.0);
+	}
+
+	#[test]
+	fn flat_ground_faces_up() {
+		let texture = flat(4, 3, 1234.0).hillshade(10.0, 1.0);
+		assert_eq!((texture.width, texture.height), (4, 3));
+		// Straight up is lit by the z component of the light
+		let shade = (60.0 + 195.0 / 3f32.sqrt()) as u8;
+		for pixel in texture.pixels.chunks(4) {
+			assert_eq!(pixel, [shade, shade, shade, 255]);
+		}
+		assert!(HeightMap::default().hillshade(10.0, 1.0).pixels.is_empty());
+	}
+
+	#[test]
+	fn slopes_facing_the_light_are_brighter() {
+		// Rising to the east, so facing west towards the light
+		let mut heights = flat(3, 3, 0.0);
+		for row in 0..3 {
+			for col in 0..3 {
+				heights.heights[row * 3 + col] = col as f32 * 10.0;
+			}
+		}
+		let lit = heights.hillshade(10.0, 1.0).pixels[4 * 4];
+		let level = flat(3, 3, 0.0).hillshade(10.0, 1.0).pixels[4 * 4];
+		assert!(lit > level, "{} should be brighter than {}", lit, level);
+	}
+}
@@ -0,0 +1,463 @@
+use nalgebra as na;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// Largest angle covered by one triangle of a round join or cap
+const ROUND_STEP: f32 = PI / 8.0;
+
+/// How the corners between the segments of a line are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineJoin {
+	/// Sides carried on until they meet, cut off like a bevel past the miter limit
+	Miter,
+	/// Corners cut off straight
+	Bevel,
+	Round,
+}
+
+/// How the ends of a line are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineCap {
+	/// Stops dead at the last point
+	Butt,
+	/// Carries on past the last point by half the width
+	Square,
+	/// A half circle around the last point
+	Round,
+}
+
+/// How lines are stroked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineStyle {
+	/// Width in CSS pixels, lines are widened as they're drawn so they look the same at every zoom level
+	pub width: f32,
+	pub join: LineJoin,
+	pub cap: LineCap,
+	/// Longest a miter can be as a multiple of the width, sharper corners are bevelled
+	pub miter_limit: f32,
+}
+
+impl Default for LineStyle {
+	fn default() -> Self {
+		Self {
+			width: 2.0,
+			join: LineJoin::Miter,
+			cap: LineCap::Butt,
+			miter_limit: 2.0,
+		}
+	}
+}
+
+/// Flat triangles, e.g. in tile units, before they're put on the globe
+#[derive(Clone, Debug, Default)]
+pub struct Triangles {
+	pub vertices: Vec<na::Point2<f32>>,
+	pub triangles: Vec<(usize, usize, usize)>,
+}
+
+// A ring without repeated points or a closing point, `None` if there's nothing left with any area
+fn clean_ring(ring: &[na::Point2<f32>]) -> Option<Vec<na::Point2<f32>>> {
+	let mut ring = ring.to_vec();
+	ring.dedup();
+	if ring.len() > 1 && ring.first() == ring.last() {
+		ring.pop();
+	}
+
+	let area: f32 = ring
+		.iter()
+		.zip(ring.iter().cycle().skip(1))
+		.map(|(a, b)| a.x * b.y - b.x * a.y)
+		.sum();
+	if ring.len() < 3 || area == 0.0 {
+		None
+	} else {
+		Some(ring)
+	}
+}
+
+/// Outline of a stroked line, as points on the line and which way to push each one out to give it width
+#[derive(Clone, Debug, Default)]
+pub struct Stroke {
+	/// Points on the line, several vertices share each one
+	pub centres: Vec<na::Point2<f32>>,
+	/// Offsets from the line in half widths, longer than 1.0 at the tips of miters
+	pub offsets: Vec<na::Vector2<f32>>,
+	/// Across the line from -1.0 on one side to 1.0 on the other, for smoothing the edges
+	pub edges: Vec<f32>,
+	pub triangles: Vec<(usize, usize, usize)>,
+}
+
+// The normal on the left of a direction
+fn left(dir: na::Vector2<f32>) -> na::Vector2<f32> {
+	na::Vector2::new(-dir.y, dir.x)
+}
+
+impl Stroke {
+	/// Stroke a line with a style's joins and caps, the width is left for whoever draws it
+	///
+	/// A line that ends where it starts is joined all the way round. Otherwise `caps` says whether the start and end
+	/// get caps, which they shouldn't where a line was cut off at the edge of a tile.
+	pub fn new(points: &[na::Point2<f32>], caps: [bool; 2], style: &LineStyle) -> Self {
+		let mut stroke = Self::default();
+		let mut points = points.to_vec();
+		points.dedup();
+		let closed = points.len() > 2 && points.first() == points.last();
+		if closed {
+			points.pop();
+		}
+		if points.len() < 2 {
+			return stroke;
+		}
+
+		let count = if closed { points.len() } else { points.len() - 1 };
+		let dirs: Vec<_> = (0..count)
+			.map(|i| (points[(i + 1) % points.len()] - points[i]).normalize())
+			.collect();
+
+		for (i, dir) in dirs.iter().enumerate() {
+			let normal = left(*dir);
+			let (a, b) = (points[i], points[(i + 1) % points.len()]);
+			stroke.quad([a, a, b, b], [normal, -normal, normal, -normal]);
+		}
+
+		let joins = if closed { 0..count } else { 1..count };
+		for i in joins {
+			stroke.join(points[i], dirs[(i + count - 1) % count], dirs[i], style);
+		}
+
+		if !closed {
+			if caps[0] {
+				stroke.cap(points[0], -dirs[0], style.cap);
+			}
+			if caps[1] {
+				stroke.cap(points[points.len() - 1], dirs[count - 1], style.cap);
+			}
+		}
+		stroke
+	}
+
+	fn push(&mut self, centre: na::Point2<f32>, offset: na::Vector2<f32>, edge: f32) {
+		self.centres.push(centre);
+		self.offsets.push(offset);
+		self.edges.push(edge);
+	}
+
+	// Two triangles across the line, the first two corners on the left and right and the last two beyond them
+	fn quad(&mut self, centres: [na::Point2<f32>; 4], offsets: [na::Vector2<f32>; 4]) {
+		let first = self.centres.len();
+		for (i, (centre, offset)) in centres.iter().zip(&offsets).enumerate() {
+			self.push(*centre, *offset, if i % 2 == 0 { 1.0 } else { -1.0 });
+		}
+		self.triangles.push((first, first + 1, first + 2));
+		self.triangles.push((first + 2, first + 1, first + 3));
+	}
+
+	// Triangles from `centre` out to each pair of neighbouring offsets
+	fn fan(&mut self, centre: na::Point2<f32>, offsets: &[na::Vector2<f32>]) {
+		let first = self.centres.len();
+		self.push(centre, na::Vector2::zeros(), 0.0);
+		for offset in offsets {
+			self.push(centre, *offset, 1.0);
+		}
+		for i in 1..offsets.len() {
+			self.triangles.push((first, first + i, first + i + 1));
+		}
+	}
+
+	// A round fan turning `angle` radians from `from`
+	fn arc(&mut self, centre: na::Point2<f32>, from: na::Vector2<f32>, angle: f32) {
+		let steps = (angle.abs() / ROUND_STEP).ceil().max(1.0) as usize;
+		let offsets: Vec<_> = (0..=steps)
+			.map(|i| na::Rotation2::new(angle * i as f32 / steps as f32) * from)
+			.collect();
+		self.fan(centre, &offsets);
+	}
+
+	/// Add another stroke's triangles to this one
+	pub fn append(&mut self, other: Stroke) {
+		let offset = self.centres.len();
+		self.centres.extend(other.centres);
+		self.offsets.extend(other.offsets);
+		self.edges.extend(other.edges);
+		self.triangles
+			.extend(other.triangles.into_iter().map(|(a, b, c)| (a + offset, b + offset, c + offset)));
+	}
+
+	// Fill the gap on the outside of a corner, the inside is already covered where the segments overlap
+	fn join(&mut self, centre: na::Point2<f32>, dir0: na::Vector2<f32>, dir1: na::Vector2<f32>, style: &LineStyle) {
+		let cross = dir0.perp(&dir1);
+		if cross.abs() < 1e-6 && dir0.dot(&dir1) > 0.0 {
+			// Straight on, the segments already meet
+			return;
+		}
+
+		// A left turn opens a gap on the right
+		let side = if cross > 0.0 { -1.0 } else { 1.0 };
+		let outer0 = left(dir0) * side;
+		let outer1 = left(dir1) * side;
+		match style.join {
+			LineJoin::Round => self.arc(centre, outer0, outer0.perp(&outer1).atan2(outer0.dot(&outer1))),
+			LineJoin::Miter => {
+				let mid = outer0 + outer1;
+				let len = if mid.norm() > 1e-6 { 1.0 / mid.normalize().dot(&outer0) } else { f32::INFINITY };
+				if len <= style.miter_limit {
+					self.fan(centre, &[outer0, mid.normalize() * len, outer1]);
+				} else {
+					self.fan(centre, &[outer0, outer1]);
+				}
+			}
+			LineJoin::Bevel => self.fan(centre, &[outer0, outer1]),
+		}
+	}
+
+	// The end of a line heading out along `dir`
+	fn cap(&mut self, centre: na::Point2<f32>, dir: na::Vector2<f32>, cap: LineCap) {
+		let normal = left(dir);
+		match cap {
+			LineCap::Butt => {}
+			LineCap::Square => self.quad([centre; 4], [normal, -normal, normal + dir, dir - normal]),
+			LineCap::Round => self.arc(centre, normal, -PI),
+		}
+	}
+}
+
+impl Triangles {
+	/// Triangulate a polygon with earcut, the first ring is the exterior and the rest are holes
+	///
+	/// Rings can be open or closed. Rings with no area are left out, all of it if that's the exterior.
+	pub fn fill_polygon(rings: &[Vec<na::Point2<f32>>]) -> Self {
+		let exterior = match rings.first().and_then(|ring| clean_ring(ring)) {
+			Some(exterior) => exterior,
+			None => return Self::default(),
+		};
+
+		let mut vertices = exterior;
+		let mut holes = vec![];
+		for hole in rings[1..].iter().filter_map(|ring| clean_ring(ring)) {
+			holes.push(vertices.len());
+			vertices.extend(hole);
+		}
+
+		let coords: Vec<f32> = vertices.iter().flat_map(|p| vec![p.x, p.y]).collect();
+		let indices = match earcutr::earcut(&coords, &holes, 2) {
+			Ok(indices) => indices,
+			Err(_) => return Self::default(),
+		};
+
+		Self {
+			vertices,
+			triangles: indices.chunks_exact(3).map(|t| (t[0], t[1], t[2])).collect(),
+		}
+	}
+
+	/// Add another set of triangles to these ones
+	pub fn append(&mut self, other: Triangles) {
+		let offset = self.vertices.len();
+		self.vertices.extend(other.vertices);
+		self.triangles
+			.extend(other.triangles.into_iter().map(|(a, b, c)| (a + offset, b + offset, c + offset)));
+	}
+
+	/// Split triangles in half along their edges until `split` doesn't want any edge split any more
+	///
+	/// `split` is given the ends of an edge and has to give the same answer either way round. Then whether an edge
+	/// is split only depends on the edge, and the midpoint is shared, so neighbouring triangles still meet without
+	/// cracks.
+	pub fn subdivide(mut self, split: impl Fn(na::Point2<f32>, na::Point2<f32>) -> bool) -> Self {
+		let mut midpoints = HashMap::new();
+		let mut todo = std::mem::take(&mut self.triangles);
+		let mut done = Vec::with_capacity(todo.len());
+
+		let vertices = &mut self.vertices;
+		let mut midpoint = |a: usize, b: usize| -> Option<usize> {
+			if !split(vertices[a], vertices[b]) {
+				return None;
+			}
+			let key = (a.min(b), a.max(b));
+			Some(*midpoints.entry(key).or_insert_with(|| {
+				vertices.push(na::center(&vertices[a], &vertices[b]));
+				vertices.len() - 1
+			}))
+		};
+
+		while let Some((a, b, c)) = todo.pop() {
+			match (midpoint(a, b), midpoint(b, c), midpoint(c, a)) {
+				(None, None, None) => done.push((a, b, c)),
+				(Some(ab), None, None) => todo.extend(&[(a, ab, c), (ab, b, c)]),
+				(None, Some(bc), None) => todo.extend(&[(b, bc, a), (bc, c, a)]),
+				(None, None, Some(ca)) => todo.extend(&[(c, ca, b), (ca, a, b)]),
+				(Some(ab), Some(bc), None) => todo.extend(&[(a, ab, c), (ab, bc, c), (ab, b, bc)]),
+				(None, Some(bc), Some(ca)) => todo.extend(&[(b, bc, a), (bc, ca, a), (bc, c, ca)]),
+				(Some(ab), None, Some(ca)) => todo.extend(&[(c, ca, b), (ca, ab, b), (ca, a, ab)]),
+				(Some(ab), Some(bc), Some(ca)) => todo.extend(&[(a, ab, ca), (ab, b, bc), (ca, bc, c), (ab, bc, ca)]),
+			}
+		}
+
+		self.triangles = done;
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn points(coords: &[(f32, f32)]) -> Vec<na::Point2<f32>> {
+		coords.iter().map(|&(x, y)| na::Point2::new(x, y)).collect()
+	}
+
+	fn area(vertices: &[na::Point2<f32>], triangles: &[(usize, usize, usize)]) -> f32 {
+		triangles
+			.iter()
+			.map(|&(a, b, c)| (vertices[b] - vertices[a]).perp(&(vertices[c] - vertices[a])).abs() / 2.0)
+			.sum()
+	}
+
+	#[test]
+	fn fills_polygons_with_holes() {
+		let exterior = points(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)]);
+		let hole = points(&[(2.0, 2.0), (2.0, 8.0), (8.0, 8.0), (8.0, 2.0)]);
+		let fill = Triangles::fill_polygon(&[exterior.clone(), hole]);
+		// The closing point is dropped, and a ring with n points and h holes takes n + 2h - 2 triangles
+		assert_eq!(fill.vertices.len(), 8);
+		assert_eq!(fill.triangles.len(), 8);
+		assert_eq!(area(&fill.vertices, &fill.triangles), 64.0);
+
+		// Holes with no area are left out
+		let flat = points(&[(1.0, 1.0), (2.0, 2.0), (3.0, 3.0)]);
+		let fill = Triangles::fill_polygon(&[exterior, flat.clone()]);
+		assert_eq!(fill.triangles.len(), 2);
+		assert_eq!(area(&fill.vertices, &fill.triangles), 100.0);
+
+		assert!(Triangles::fill_polygon(&[flat]).triangles.is_empty());
+		assert!(Triangles::fill_polygon(&[]).triangles.is_empty());
+	}
+
+	fn longest_offset(stroke: &Stroke) -> f32 {
+		stroke.offsets.iter().map(|offset| offset.norm()).fold(0.0, f32::max)
+	}
+
+	#[test]
+	fn joins() {
+		let square_corner = points(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+		let style = |join| LineStyle {
+			join,
+			..LineStyle::default()
+		};
+
+		// A right angle's miter is sqrt(2) half widths long, within the default limit of 2
+		let miter = Stroke::new(&square_corner, [false, false], &style(LineJoin::Miter));
+		assert_eq!(miter.triangles.len(), 6);
+		assert!((longest_offset(&miter) - 2.0f32.sqrt()).abs() < 1e-5);
+
+		// Doubling back would need a miter far past the limit, so it's bevelled
+		let sharp = points(&[(0.0, 0.0), (10.0, 0.0), (0.0, 1.0)]);
+		let bevelled = Stroke::new(&sharp, [false, false], &style(LineJoin::Miter));
+		assert_eq!(bevelled.triangles.len(), 5);
+		assert!(longest_offset(&bevelled) <= 1.0 + 1e-5);
+
+		let bevel = Stroke::new(&square_corner, [false, false], &style(LineJoin::Bevel));
+		assert_eq!(bevel.triangles.len(), 5);
+
+		// A quarter turn in steps of an eighth of a half turn
+		let round = Stroke::new(&square_corner, [false, false], &style(LineJoin::Round));
+		assert_eq!(round.triangles.len(), 8);
+		assert!(longest_offset(&round) <= 1.0 + 1e-5);
+
+		// Going straight on needs no join, and neither do repeated points
+		let straight = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (2.0, 0.0)]);
+		assert_eq!(Stroke::new(&straight, [false, false], &style(LineJoin::Miter)).triangles.len(), 4);
+	}
+
+	#[test]
+	fn caps() {
+		let line = points(&[(0.0, 0.0), (1.0, 0.0)]);
+		let style = |cap| LineStyle {
+			cap,
+			..LineStyle::default()
+		};
+		let count = |caps, cap| Stroke::new(&line, caps, &style(cap)).triangles.len();
+
+		assert_eq!(count([true, true], LineCap::Butt), 2);
+		assert_eq!(count([true, true], LineCap::Square), 6);
+		assert_eq!(count([true, false], LineCap::Square), 4);
+		assert_eq!(count([true, true], LineCap::Round), 18);
+		assert_eq!(count([false, false], LineCap::Round), 2);
+
+		// Square caps reach half a width past the end
+		let square = Stroke::new(&line, [false, true], &style(LineCap::Square));
+		let furthest = square
+			.centres
+			.iter()
+			.zip(&square.offsets)
+			.map(|(centre, offset)| centre.x + offset.x)
+			.fold(f32::MIN, f32::max);
+		assert_eq!(furthest, 2.0);
+
+		// Too short to have a direction
+		assert!(Stroke::new(&points(&[(1.0, 1.0), (1.0, 1.0)]), [true, true], &style(LineCap::Round))
+			.triangles
+			.is_empty());
+	}
+
+	#[test]
+	fn closed_lines_are_joined_all_the_way_round() {
+		let square = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)]);
+		let stroke = Stroke::new(&square, [true, true], &LineStyle::default());
+		// Four sides and four mitred corners, with no caps
+		assert_eq!(stroke.triangles.len(), 16);
+	}
+
+	#[test]
+	fn subdividing_leaves_no_cracks() {
+		let square = Triangles {
+			vertices: points(&[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]),
+			triangles: vec![(0, 1, 2), (0, 2, 3)],
+		};
+		// Edges of different lengths are split a different number of times, so neighbouring triangles don't match up
+		let fine = square.subdivide(|a, b| (b - a).norm() > 0.7);
+		let vertices = &fine.vertices;
+		assert!((area(vertices, &fine.triangles) - 4.0).abs() < 1e-5);
+
+		// Midpoints are shared rather than added again for each triangle
+		for (i, a) in vertices.iter().enumerate() {
+			assert!(vertices[i + 1..].iter().all(|b| a != b));
+		}
+
+		let signed = |&(a, b, c): &(usize, usize, usize)| {
+			(vertices[b] - vertices[a]).perp(&(vertices[c] - vertices[a]))
+		};
+		let mut edges = HashMap::new();
+		for triangle in &fine.triangles {
+			// Still wound the same way
+			assert!(signed(triangle) > 0.0);
+			let (a, b, c) = *triangle;
+			for &(p, q) in &[(a, b), (b, c), (c, a)] {
+				*edges.entry((p.min(q), p.max(q))).or_insert(0) += 1;
+			}
+		}
+		for (&(p, q), &count) in &edges {
+			let (p, q) = (vertices[p], vertices[q]);
+			// Edges inside the square are shared by two triangles, so nothing is left open
+			let outside = (p.x == q.x && (p.x == 0.0 || p.x == 2.0)) || (p.y == q.y && (p.y == 0.0 || p.y == 2.0));
+			assert_eq!(count, if outside { 1 } else { 2 });
+			// And no vertex sits part way along an edge where it could open a crack
+			for v in vertices {
+				let t = (v - p).dot(&(q - p)) / (q - p).norm_squared();
+				let on_edge = (v - p).perp(&(q - p)).abs() < 1e-6 && t > 1e-6 && t < 1.0 - 1e-6;
+				assert!(!on_edge, "{:?} is on the edge from {:?} to {:?}", v, p, q);
+			}
+		}
+		assert!(fine.triangles.len() > 2);
+	}
+
+	#[test]
+	fn appending_offsets_indices() {
+		let square = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+		let mut fill = Triangles::fill_polygon(std::slice::from_ref(&square));
+		fill.append(Triangles::fill_polygon(&[square]));
+		assert_eq!(fill.vertices.len(), 8);
+		assert_eq!(fill.triangles.len(), 4);
+		assert!(fill.triangles[2..].iter().all(|&(a, b, c)| a >= 4 && b >= 4 && c >= 4));
+	}
+}
@@ -1,177 +1,851 @@
-use crate::mesh::Mesh;
-use crate::geometry::{lonlat_to_point, pixel_to_lonlat};
+use crate::error::{Error, Result};
+use crate::mesh::{Extrusion, Mesh, Texture};
+use crate::geometry::clip::{clip_geometry, clip_line_ends, Bounds};
+use crate::geometry::mvt::{decode_layer, Feature, Geometry, Point, Value};
+use crate::geometry::{east_north, lonlat_to_point};
+use crate::mercator::TileCoord;
 use crate::protos::vector_tile::Tile as VectorTile;
+use crate::terrain::{cell_size, HeightMap, EARTH_RADIUS};
+use crate::tessellate::{LineStyle, Stroke, Triangles};
 use nalgebra as na;
-use std::f32::consts::PI;
+use std::rc::Rc;
 
-const MOVE_TO: u32 = 0x1;
-const LINE_TO: u32 = 0x2;
-const CLOSE_PATH: u32 = 0x7;
+// Grid cells along each side of a raster patch, enough to follow the curve of the globe at low zoom
+const RASTER_SEGMENTS: usize = 16;
+// Grid cells along each side of a terrain patch, a vertex every 8 pixels of a 256 pixel tile
+const TERRAIN_SEGMENTS: usize = 32;
+// Height of draped lines above the terrain surface, in globe radii, so they don't sink into it between vertices
+const DRAPE_OFFSET: f32 = 0.0001;
+// Height of lines above fills, in globe radii, so they're drawn on top of them
+const LINE_OFFSET: f32 = 0.00002;
+// How far past the edge of the tile features are kept by default, in 1/4096ths of a tile
+const DEFAULT_BUFFER: u32 = 64;
+// Furthest a straight edge can stray from the curve of the globe, in pixels of a 256 pixel tile
+const MAX_ERROR_PIXELS: f32 = 0.25;
+// Edges shorter than a pixel of a 256 pixel tile are never split, so nothing gets split forever
+const MIN_SPLIT_LEN: f32 = 1.0 / 256.0;
 
+// Add points along a line, halving each piece for as long as `split` says it needs it
+fn split_line(
+	points: &[na::Point2<f32>],
+	split: &impl Fn(na::Point2<f32>, na::Point2<f32>) -> bool,
+) -> Vec<na::Point2<f32>> {
+	fn bisect(
+		p0: na::Point2<f32>,
+		p1: na::Point2<f32>,
+		split: &impl Fn(na::Point2<f32>, na::Point2<f32>) -> bool,
+		out: &mut Vec<na::Point2<f32>>,
+	) {
+		if split(p0, p1) {
+			let mid = na::center(&p0, &p1);
+			bisect(p0, mid, split, out);
+			bisect(mid, p1, split, out);
+		} else {
+			out.push(p1);
+		}
+	}
+
+	let mut out = points[..1].to_vec();
+	for pair in points.windows(2) {
+		bisect(pair[0], pair[1], split, &mut out);
+	}
+	out
+}
+
+// The part of an image a raster tile shows, overzoomed tiles only use a corner of their ancestor's
 #[derive(Clone, Debug)]
+struct Raster {
+	texture: Rc<Texture>,
+	uv_min: na::Point2<f32>,
+	uv_max: na::Point2<f32>,
+}
+
+// The part of a height map a terrain tile covers, overzoomed tiles only use a corner of their ancestor's
+#[derive(Clone, Debug)]
+struct Terrain {
+	heights: Rc<HeightMap>,
+	// Shaded relief of the whole height map, so the surface stands out from the lines draped over it
+	shading: Rc<Texture>,
+	exaggeration: f32,
+	uv_min: na::Point2<f32>,
+	uv_max: na::Point2<f32>,
+}
+
+impl Terrain {
+	// Distance from the centre of the globe at a point in tile units
+	fn radius(&self, p: na::Point2<f32>) -> f32 {
+		let uv = self.uv_min + (self.uv_max - self.uv_min).component_mul(&p.coords);
+		1.0 + self.heights.sample(uv.x, uv.y) * self.exaggeration / EARTH_RADIUS
+	}
+}
+
+/// How vector tiles are turned into meshes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VectorOptions {
+	/// How far past the edge of the tile features are kept, in 1/4096ths of a tile
+	///
+	/// Only the features keep the buffer, for cutting up deeper tiles. Meshes always stop at the edge of the tile.
+	pub buffer: u32,
+	/// How every line in the tile is stroked
+	pub line_style: LineStyle,
+}
+
+impl Default for VectorOptions {
+	fn default() -> Self {
+		Self {
+			buffer: DEFAULT_BUFFER,
+			line_style: LineStyle::default(),
+		}
+	}
+}
+
+/// One named layer of a vector tile, e.g. `water` or `road`
+#[derive(Clone, Debug, Default)]
+pub struct Layer {
+	name: String,
+	extent: u32,
+	buffer: u32,
+	line_style: LineStyle,
+	// Kept so the tile can be cut up for deeper zoom levels
+	features: Vec<Feature>,
+	mesh: Mesh,
+}
+
+impl Layer {
+	/// A layer that hasn't been turned into a mesh yet, with features in coordinates from 0 to `extent`
+	pub(crate) fn new(name: &str, extent: u32, features: Vec<Feature>) -> Self {
+		Self {
+			name: name.to_string(),
+			extent,
+			buffer: 0,
+			line_style: LineStyle::default(),
+			features,
+			mesh: Mesh::new(),
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Units across the tile in the source's coordinates
+	pub fn extent(&self) -> u32 {
+		self.extent
+	}
+
+	/// How far past the edge of the tile features are kept, in the same units as the extent
+	pub fn buffer(&self) -> u32 {
+		self.buffer
+	}
+
+	/// The same layer with features reaching `buffer` units past the edge of the tile
+	pub(crate) fn with_buffer(self, buffer: u32) -> Self {
+		Self { buffer, ..self }
+	}
+
+	/// How the layer's lines are stroked
+	pub fn line_style(&self) -> &LineStyle {
+		&self.line_style
+	}
+
+	/// The same layer with its lines stroked in `line_style`
+	pub(crate) fn with_line_style(self, line_style: LineStyle) -> Self {
+		Self { line_style, ..self }
+	}
+
+	pub fn features(&self) -> &[Feature] {
+		&self.features
+	}
+
+	pub fn mesh(&self) -> &Mesh {
+		&self.mesh
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.features.is_empty()
+	}
+
+	fn to_tile(&self, p: &Point) -> na::Point2<f32> {
+		let extent = self.extent as f32;
+		na::Point2::new(p.x as f32 / extent, p.y as f32 / extent)
+	}
+
+	// Every feature cut off exactly at the edges of the tile, so neighbouring tiles meet without overlapping
+	fn clipped_geometry(&self) -> impl Iterator<Item = Geometry> + '_ {
+		let bounds = Bounds::tile(self.extent, 0);
+		self.features
+			.iter()
+			.filter_map(move |feature| clip_geometry(&feature.geometry, &bounds))
+	}
+
+	// Every line stroked in tile units. Polygons are filled rather than outlined.
+	fn strokes(&self, style: &LineStyle, split: impl Fn(na::Point2<f32>, na::Point2<f32>) -> bool) -> Stroke {
+		let bounds = Bounds::tile(self.extent, 0);
+		let mut strokes = Stroke::default();
+		for feature in &self.features {
+			let lines = match &feature.geometry {
+				Geometry::LineString(line) => std::slice::from_ref(line),
+				Geometry::MultiLineString(lines) => &lines[..],
+				_ => continue,
+			};
+			// Lines cut off at the edge carry on in the next tile, so they don't get caps there
+			for (line, cut) in lines.iter().flat_map(|line| clip_line_ends(line, &bounds)) {
+				let points: Vec<_> = line.iter().map(|p| self.to_tile(p)).collect();
+				strokes.append(Stroke::new(&split_line(&points, &split), [!cut[0], !cut[1]], style));
+			}
+		}
+		strokes
+	}
+
+	// Every polygon triangulated in tile units
+	fn fills(&self) -> Triangles {
+		let mut fills = Triangles::default();
+		for geometry in self.clipped_geometry() {
+			let polygons = match &geometry {
+				Geometry::Polygon(polygon) => std::slice::from_ref(polygon),
+				Geometry::MultiPolygon(polygons) => &polygons[..],
+				_ => continue,
+			};
+			for polygon in polygons {
+				let rings: Vec<Vec<_>> = polygon
+					.rings()
+					.map(|ring| ring.iter().map(|p| self.to_tile(p)).collect())
+					.collect();
+				fills.append(Triangles::fill_polygon(&rings));
+			}
+		}
+		fills
+	}
+
+	// Same layer with its mesh built
+	fn build(self, build_mesh: impl Fn(&Layer) -> Mesh) -> Self {
+		Self {
+			mesh: build_mesh(&self),
+			..self
+		}
+	}
+
+	fn byte_size(&self) -> usize {
+		let features: usize = self
+			.features
+			.iter()
+			.map(|feature| {
+				feature.geometry.point_count() * std::mem::size_of::<Point>()
+					+ feature.properties.len() * std::mem::size_of::<(String, Value)>()
+			})
+			.sum();
+		self.mesh.byte_size() + features
+	}
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Tile {
+	// Raster or terrain surface, the layers are drawn on top of it
 	mesh: Mesh,
+	layers: Vec<Layer>,
+	raster: Option<Raster>,
+	terrain: Option<Terrain>,
 }
 
 impl Tile {
 	pub fn new() -> Self {
-		Self { mesh: Mesh::new() }
+		Self::default()
 	}
 
+	/// Everything in the tile as one mesh
 	pub fn mesh(&self) -> Mesh {
-		self.mesh.clone()
+		let mut mesh = self.mesh.clone();
+		for layer in &self.layers {
+			mesh.append(layer.mesh.clone());
+		}
+		mesh
+	}
+
+	/// The tile's surface with only the named layers on it
+	pub fn layers_mesh(&self, names: &[&str]) -> Mesh {
+		let mut mesh = self.mesh.clone();
+		for layer in self.layers.iter().filter(|layer| names.contains(&layer.name())) {
+			mesh.append(layer.mesh.clone());
+		}
+		mesh
+	}
+
+	pub fn layers(&self) -> &[Layer] {
+		&self.layers
+	}
+
+	pub fn layer(&self, name: &str) -> Option<&Layer> {
+		self.layers.iter().find(|layer| layer.name == name)
+	}
+
+	/// Move another tile's layers into this one, named `{namespace}:{layer}` unless the namespace is empty
+	pub(crate) fn merge_layers(&mut self, other: Tile, namespace: &str) {
+		self.layers.extend(other.layers.into_iter().map(|layer| match namespace {
+			"" => layer,
+			_ => Layer {
+				name: format!("{}:{}", namespace, layer.name),
+				..layer
+			},
+		}));
+	}
+
+	/// Approximate memory used by the tile's mesh, geometry and images
+	///
+	/// Overzoomed tiles share their ancestor's image and height map, so each only counts the part it covers. Together
+	/// they add up to the whole thing once, rather than once per tile.
+	pub fn byte_size(&self) -> usize {
+		let part = |bytes: usize, uv_min: na::Point2<f32>, uv_max: na::Point2<f32>| {
+			let size = uv_max - uv_min;
+			(bytes as f32 * size.x * size.y).ceil() as usize
+		};
+		let raster = self
+			.raster
+			.as_ref()
+			.map_or(0, |r| part(r.texture.pixels.len(), r.uv_min, r.uv_max));
+		let terrain = self.terrain.as_ref().map_or(0, |t| {
+			let heights = t.heights.heights.len() * std::mem::size_of::<f32>();
+			part(heights + t.shading.pixels.len(), t.uv_min, t.uv_max)
+		});
+		let layers: usize = self
+			.layers
+			.iter()
+			.map(Layer::byte_size)
+			.sum();
+		self.mesh.byte_size() + layers + raster + terrain
 	}
 
 	pub fn vertices(&self) -> Vec<f32> {
-		self.mesh.vertices_as_vec()
+		self.mesh().vertices_as_vec()
 	}
 
 	pub fn triangles(&self) -> Vec<u32> {
-		self.mesh.triangles_as_vec()
+		self.mesh().triangles_as_vec()
+	}
+
+	pub fn from_vector_tile(raw: VectorTile, x: i32, y: i32, z: i32) -> Result<Self> {
+		Self::from_vector_tile_with_options(raw, &VectorOptions::default(), x, y, z)
 	}
 
-	pub fn from_vector_tile<'a>(raw: VectorTile<'a>, x: i32, y: i32, z: i32) -> Self {
-		let mut mesh = Mesh::new();
-		if raw.layers.len() == 0 {
-			return Self { mesh };
+	/// A vector tile meshed with `options`
+	pub fn from_vector_tile_with_options(
+		raw: VectorTile,
+		options: &VectorOptions,
+		x: i32,
+		y: i32,
+		z: i32,
+	) -> Result<Self> {
+		let layers = raw
+			.layers
+			.iter()
+			.map(|layer| {
+				if layer.extent == 0 {
+					return Err(Error::Geometry(format!("Layer '{}' has an extent of 0", layer.name)));
+				}
+				let buffer = (options.buffer as u64 * layer.extent as u64 / 4096) as u32;
+				let bounds = Bounds::tile(layer.extent, buffer);
+				// One bad feature shouldn't cost us the rest of the layer
+				let features = decode_layer(layer)
+					.into_iter()
+					.filter_map(|feature| match feature {
+						Ok(feature) => Some(feature),
+						Err(err) => {
+							crate::log(&format!("Skipping feature in layer '{}': {}", layer.name, err));
+							None
+						}
+					})
+					.filter_map(|feature| {
+						Some(Feature {
+							geometry: clip_geometry(&feature.geometry, &bounds)?,
+							..feature
+						})
+					})
+					.collect();
+				Ok(Layer::new(&layer.name, layer.extent, features)
+					.with_buffer(buffer)
+					.with_line_style(options.line_style))
+			})
+			.collect::<Result<_>>()?;
+		Ok(Self::from_layers(layers, TileCoord::new(x, y, z)))
+	}
+
+	/// A raster tile, drawn as a patch of the globe with the image stretched over it
+	pub fn from_raster(texture: Texture, x: i32, y: i32, z: i32) -> Self {
+		Self::from_raster_part(
+			Raster {
+				texture: Rc::new(texture),
+				uv_min: na::Point2::new(0.0, 0.0),
+				uv_max: na::Point2::new(1.0, 1.0),
+			},
+			TileCoord::new(x, y, z),
+		)
+	}
+
+	fn from_raster_part(raster: Raster, coord: TileCoord) -> Self {
+		let n = RASTER_SEGMENTS;
+		let mut vertices = Vec::with_capacity((n + 1) * (n + 1));
+		let mut uvs = Vec::with_capacity((n + 1) * (n + 1));
+		let uv_size = raster.uv_max - raster.uv_min;
+
+		// Image rows are evenly spaced in mercator, same as tile units, so the UVs can be spread evenly too
+		for row in 0..=n {
+			for col in 0..=n {
+				let t = na::Vector2::new(col as f32 / n as f32, row as f32 / n as f32);
+				let lonlat = coord.pixel_to_lonlat(&na::Point2::from(t), 1);
+				vertices.push(lonlat_to_point(lonlat.as_point(), 1.0));
+				uvs.push(raster.uv_min + uv_size.component_mul(&t));
+			}
+		}
+
+		Self {
+			mesh: Mesh::textured(vertices, uvs, grid_triangles(n), raster.texture.clone()),
+			layers: vec![],
+			raster: Some(raster),
+			terrain: None,
+		}
+	}
+
+	/// A terrain tile, drawn as a patch of the globe pushed out by the elevation at each point
+	///
+	/// Heights are multiplied by `exaggeration` before being scaled to the globe.
+	pub fn from_terrain(heights: HeightMap, exaggeration: f32, x: i32, y: i32, z: i32) -> Self {
+		let coord = TileCoord::new(x, y, z);
+		let lat = coord.center().lat();
+		let shading = heights.hillshade(cell_size(lat, z, heights.width), exaggeration);
+		Self::from_terrain_part(
+			Terrain {
+				heights: Rc::new(heights),
+				shading: Rc::new(shading),
+				exaggeration,
+				uv_min: na::Point2::new(0.0, 0.0),
+				uv_max: na::Point2::new(1.0, 1.0),
+			},
+			vec![],
+			coord,
+		)
+	}
+
+	fn from_terrain_part(terrain: Terrain, layers: Vec<Layer>, coord: TileCoord) -> Self {
+		let n = TERRAIN_SEGMENTS;
+		let mut vertices = Vec::with_capacity((n + 1) * (n + 1));
+		let mut uvs = Vec::with_capacity((n + 1) * (n + 1));
+		let uv_size = terrain.uv_max - terrain.uv_min;
+
+		for row in 0..=n {
+			for col in 0..=n {
+				let t = na::Point2::new(col as f32 / n as f32, row as f32 / n as f32);
+				let lonlat = coord.pixel_to_lonlat(&t, 1);
+				vertices.push(lonlat_to_point(lonlat.as_point(), terrain.radius(t)));
+				uvs.push(terrain.uv_min + uv_size.component_mul(&t.coords));
+			}
+		}
+		let mesh = Mesh::textured(vertices, uvs, grid_triangles(n), terrain.shading.clone());
+
+		// Cut lines and fills at every grid cell so they follow the surface instead of cutting through hills
+		let max_len = 1.0 / n as f32;
+		let layers = layers
+			.into_iter()
+			.map(|layer| layer.build(|layer| layer_mesh(layer, coord, max_len, |p| terrain.radius(p) + DRAPE_OFFSET)))
+			.collect();
+
+		Self {
+			mesh,
+			layers,
+			raster: None,
+			terrain: Some(terrain),
 		}
+	}
 
-		let layer = &raw.layers[0];
-		let extent = layer.extent as f32;
+	/// Lay another tile's vector layers over this tile's terrain
+	///
+	/// The layers are drawn flat on the globe if this isn't a terrain tile.
+	pub fn drape(&self, overlay: &Tile, coord: TileCoord) -> Self {
+		match &self.terrain {
+			Some(terrain) => Self::from_terrain_part(terrain.clone(), overlay.layers.clone(), coord),
+			None => Self::from_layers(overlay.layers.clone(), coord),
+		}
+	}
 
-		// features
-		for feature in &layer.features {
-			let mut edges = vec![];
-			let mut geometry = feature.geometry.clone().into_iter();
-			let mut cursor = (0.0, 0.0);
+	/// Build a descendant of this tile by cutting out its part of the geometry and scaling it up
+	///
+	/// `parent` is this tile's coordinate, `child` has to be within it.
+	pub fn overzoom(&self, parent: TileCoord, child: TileCoord) -> Self {
+		// In i64 as 2^dz times the extent is past i32 once the child is 19 levels down
+		let dz = child.z - parent.z;
+		let scale = 1i64 << dz;
+		let (dx, dy) = (child.x as i64 - parent.x as i64 * scale, child.y as i64 - parent.y as i64 * scale);
+		let offset = na::Vector2::new(dx as f32, dy as f32);
 
-			// geometry
-			while let Some(cmdint) = geometry.next() {
-				let cmd = (cmdint & 0x7) as u32;
-				let count = (cmdint >> 3) as u32;
+		// Raster tiles show a smaller part of the same image
+		if let Some(raster) = &self.raster {
+			let size = (raster.uv_max - raster.uv_min) / scale as f32;
+			let uv_min = raster.uv_min + size.component_mul(&offset);
+			let part = Raster {
+				texture: raster.texture.clone(),
+				uv_min,
+				uv_max: uv_min + size,
+			};
+			return Self::from_raster_part(part, child);
+		}
 
-				let make_point = |cursor: (f32, f32)| {
-					// pixels coords range from 0.0 to 1.0
-					pixel_to_lonlat(
-						&na::Point2::new(x as f32 + cursor.0, y as f32 + cursor.1),
-						1.0 + z as f32,
-					)
+		let layers = self
+			.layers
+			.iter()
+			.map(|layer| {
+				// Whole units of the layer's extent, so the geometry stays exact
+				let extent = layer.extent as i64;
+				let bounds = Bounds::tile(layer.extent, layer.buffer);
+				// Anything pushed past i32 is far outside the child and gets clipped away
+				let scaled = |v: i32, d: i64| {
+					(v as i64 * scale - d * extent).clamp(i32::MIN as i64, i32::MAX as i64) as i32
 				};
+				let to_child = |p: &Point| Point::new(scaled(p.x, dx), scaled(p.y, dy));
+				let features = layer
+					.features
+					.iter()
+					.filter_map(|feature| {
+						Some(Feature {
+							id: feature.id,
+							geometry: clip_geometry(&feature.geometry.map(to_child), &bounds)?,
+							properties: feature.properties.clone(),
+						})
+					})
+					.collect();
+				Layer::new(&layer.name, layer.extent, features)
+					.with_buffer(layer.buffer)
+					.with_line_style(layer.line_style)
+			})
+			.collect();
 
-				let mut add_edge = |p0: na::Point2<f32>, p1: na::Point2<f32>| {
-					let line = p0 - p1;
-					let len = line.norm().abs();
-					let norm = line.normalize();
-					if (norm.x.abs() == 1.0 || norm.y.abs() == 1.0) && len > 3.0 {
-						// Weird axis aligned line (borders)
-					} else if len > 10.0 {
-						// Weird long line ??
-					} else if len == 0.0 {
-						// Nothing to draw
-					} else {
-						edges.push((p0, p1));
-					}
+		// Terrain uses a smaller part of the same height map, with any draped layers cut down to fit
+		match &self.terrain {
+			Some(terrain) => {
+				let size = (terrain.uv_max - terrain.uv_min) / scale as f32;
+				let uv_min = terrain.uv_min + size.component_mul(&offset);
+				let part = Terrain {
+					uv_min,
+					uv_max: uv_min + size,
+					..terrain.clone()
 				};
+				Self::from_terrain_part(part, layers, child)
+			}
+			None => Self::from_layers(layers, child),
+		}
+	}
 
-				let mut line_start = make_point(cursor);
-				let mut line_closed = true;
-				// command
-				for _ in 0..count {
-					match cmd {
-						MOVE_TO => {
-							if !line_closed {
-								let p0 = make_point(cursor);
-								let p1 = line_start;
-								add_edge(p0, p1);
-							}
-
-							let param = geometry.next().unwrap() as i32;
-							let arg0 = (param >> 1) ^ (-(param & 1));
-							let param = geometry.next().unwrap() as i32;
-							let arg1 = (param >> 1) ^ (-(param & 1));
-
-							cursor.0 += arg0 as f32 / extent;
-							cursor.1 += arg1 as f32 / extent;
-							line_start = make_point(cursor);
-						}
-						LINE_TO => {
-							line_closed = false;
-							let param = geometry.next().unwrap() as i32;
-							let arg0 = (param >> 1) ^ (-(param & 1));
-							let param = geometry.next().unwrap() as i32;
-							let arg1 = (param >> 1) ^ (-(param & 1));
-
-							let p0 = make_point(cursor);
-
-							cursor.0 += arg0 as f32 / extent;
-							cursor.1 += arg1 as f32 / extent;
-
-							let p1 = make_point(cursor);
-
-							let border = 0.0;
-
-							if p0.x >= 180.0 - border || p1.x >= 180.0 - border {
-								continue;
-							}
-							if p0.x <= -180.0 + border || p1.x <= -180.0 + border {
-								continue;
-							}
-							if p0.y >= 90.0 - border || p1.y >= 90.0 - border {
-								continue;
-							}
-							if p0.y <= -90.0 + border || p1.y <= -90.0 + border {
-								continue;
-							}
-
-							add_edge(p0, p1);
-						}
-						CLOSE_PATH => {
-							line_closed = true;
-							let p0 = make_point(cursor);
-							let p1 = line_start;
-							add_edge(p0, p1);
-						}
-						_ => panic!("Unknown command {}", cmd),
-					}
-				}
+	pub(crate) fn from_layers(layers: Vec<Layer>, coord: TileCoord) -> Self {
+		Self {
+			mesh: Mesh::new(),
+			layers: layers
+				.into_iter()
+				.map(|layer| layer.build(|layer| layer_mesh(layer, coord, f32::INFINITY, |_| 1.0)))
+				.collect(),
+			raster: None,
+			terrain: None,
+		}
+	}
+}
 
-				if !line_closed {
-					let p0 = make_point(cursor);
-					let p1 = line_start;
-					add_edge(p0, p1);
-				}
-			}
+// Two triangles for each cell of an `n` by `n` grid of vertices laid out row by row
+fn grid_triangles(n: usize) -> Vec<(usize, usize, usize)> {
+	let mut triangles = Vec::with_capacity(n * n * 2);
+	for row in 0..n {
+		for col in 0..n {
+			let i = row * (n + 1) + col;
+			triangles.push((i, i + n + 1, i + 1));
+			triangles.push((i + 1, i + n + 1, i + n + 2));
+		}
+	}
+	triangles
+}
 
-			let thickness = 0.2;
-			for edge in &edges {
-				let p0 = na::Point2::new(edge.0.x, edge.0.y);
-				let p1 = na::Point2::new(edge.1.x, edge.1.y);
-
-				let dir = na::Matrix3::new_rotation(PI / 2.0).transform_vector(&((p0 - p1).normalize() * thickness));
-				let mat = na::Matrix3::new_translation(&dir);
-				let p2 = mat.transform_point(&p0);
-				let p3 = mat.transform_point(&p1);
-				let p0 = mat.try_inverse().unwrap().transform_point(&p0);
-				let p1 = mat.try_inverse().unwrap().transform_point(&p1);
-
-				mesh.vertices_mut().push(lonlat_to_point(&p0));
-				mesh.vertices_mut().push(lonlat_to_point(&p1));
-				mesh.vertices_mut().push(lonlat_to_point(&p2));
-				mesh.vertices_mut().push(lonlat_to_point(&p3));
+// Whether the straight edge between two points in tile units needs splitting to follow the globe
+//
+// The error is how far the middle of the edge is from where it should be on the surface, as an angle, which is the
+// same as the distance on a globe with a radius of 1.0. Edges longer than `max_len` are always split.
+fn splits_edge(coord: TileCoord, max_len: f32) -> impl Fn(na::Point2<f32>, na::Point2<f32>) -> bool {
+	let TileCoord { x, y, z } = coord;
+	let tolerance = MAX_ERROR_PIXELS as f64 * 2.0 * std::f64::consts::PI / (256.0 * 2.0f64.powi(z));
+	// Worked out in f64, at high zoom levels the error is far smaller than f32 rounding
+	let to_point = move |p: na::Point2<f32>| {
+		let (u, v) = ((x as f64 + p.x as f64) / 2.0f64.powi(z), (y as f64 + p.y as f64) / 2.0f64.powi(z));
+		let lon = (2.0 * u - 1.0) * std::f64::consts::PI;
+		let lat = ((1.0 - 2.0 * v) * std::f64::consts::PI).sinh().atan();
+		na::Point3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+	};
+
+	move |p0, p1| {
+		let len = (p1 - p0).norm();
+		if len > max_len {
+			return true;
+		}
+		if len < MIN_SPLIT_LEN {
+			return false;
+		}
+		let chord = na::center(&to_point(p0), &to_point(p1));
+		(to_point(na::center(&p0, &p1)) - chord).norm() > tolerance
+	}
+}
+
+// Filled polygons and stroked lines of a layer, split wherever they'd stray from the globe or are longer than `max_len`
+fn layer_mesh(layer: &Layer, coord: TileCoord, max_len: f32, radius: impl Fn(na::Point2<f32>) -> f32) -> Mesh {
+	let to_lonlat = |p: &na::Point2<f32>| *coord.pixel_to_lonlat(p, 1).as_point();
+
+	let fills = layer.fills().subdivide(splits_edge(coord, max_len));
+	let mut mesh = Mesh {
+		vertices: fills
+			.vertices
+			.iter()
+			.map(|p| lonlat_to_point(&to_lonlat(p), radius(*p)))
+			.collect(),
+		triangles: fills.triangles,
+		..Mesh::default()
+	};
+
+	// Mercator is stretched by the same amount both ways, so offsets in tile units point the same way on the globe
+	let style = layer.line_style();
+	let half_width = style.width / 2.0;
+	let strokes = layer.strokes(style, splits_edge(coord, max_len));
+	let vertices = strokes
+		.centres
+		.iter()
+		.map(|p| lonlat_to_point(&to_lonlat(p), radius(*p) + LINE_OFFSET))
+		.collect();
+	let extrusions = strokes
+		.centres
+		.iter()
+		.zip(&strokes.offsets)
+		.zip(&strokes.edges)
+		.map(|((p, offset), edge)| {
+			let (east, north) = east_north(&to_lonlat(p));
+			Extrusion {
+				offset: (east * offset.x - north * offset.y) * half_width,
+				half_width,
+				edge: *edge,
 			}
-			let step = 4;
-			for i in 0..edges.len() {
-				let p0 = i * step;
-				let p1 = i * step + 1;
-				let p2 = i * step + 2;
-				let p3 = i * step + 3;
-				mesh.triangles_mut().push((p0, p1, p2));
-				mesh.triangles_mut().push((p1, p3, p2));
-				mesh.triangles_mut().push((p2, p1, p0));
-				mesh.triangles_mut().push((p2, p3, p1));
+		})
+		.collect();
+	mesh.append(Mesh::lines(vertices, extrusions, strokes.triangles));
+	mesh
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn overzoomed_rasters_share_their_image() {
+		let image = 256 * 256 * 4;
+		let texture = Texture {
+			width: 256,
+			height: 256,
+			pixels: vec![0; image],
+		};
+		let parent = Tile::from_raster(texture, 0, 0, 1);
+		let mesh = parent.mesh.byte_size();
+		assert_eq!(parent.byte_size(), mesh + image);
+
+		let coord = TileCoord::new(0, 0, 1);
+		let children: usize = (0..16)
+			.map(|i| parent.overzoom(coord, TileCoord::new(i % 4, i / 4, 3)).byte_size() - mesh)
+			.sum();
+		assert_eq!(children, image);
+	}
+
+	#[test]
+	fn overzooming_far_past_the_parent() {
+		let parent = Tile::from_layers(
+			vec![Layer::new(
+				"places",
+				4096,
+				vec![Feature {
+					id: None,
+					geometry: Geometry::Point(Point::new(2048, 2048)),
+					properties: Default::default(),
+				}],
+			)],
+			TileCoord::new(0, 0, 0),
+		);
+
+		// The child's top left corner is the middle of the parent
+		let child = parent.overzoom(TileCoord::new(0, 0, 0), TileCoord::new(1 << 19, 1 << 19, 20));
+		let features = child.layer("places").unwrap().features();
+		assert_eq!(features.len(), 1);
+		assert_eq!(features[0].geometry, Geometry::Point(Point::new(0, 0)));
+
+		let other = parent.overzoom(TileCoord::new(0, 0, 0), TileCoord::new(0, (1 << 30) - 1, 30));
+		assert!(other.layer("places").unwrap().features().is_empty());
+	}
+
+	#[test]
+	fn edges_are_split_to_follow_the_globe() {
+		let (left, right) = (na::Point2::new(0.0, 0.5), na::Point2::new(1.0, 0.5));
+
+		// Straight across the whole world strays far from the surface, across a street doesn't
+		let world = splits_edge(TileCoord::new(0, 0, 0), f32::INFINITY);
+		assert!(world(left, right));
+		assert!(world(right, left));
+		let street = splits_edge(TileCoord::new(1 << 17, 1 << 17, 18), f32::INFINITY);
+		assert!(!street(left, right));
+
+		// Anything longer than the limit is split regardless
+		let limited = splits_edge(TileCoord::new(1 << 17, 1 << 17, 18), 0.5);
+		assert!(limited(left, right));
+		assert!(!limited(left, na::Point2::new(0.25, 0.5)));
+
+		// Edges shorter than a pixel are left alone even where the globe curves the most
+		let polar = splits_edge(TileCoord::new(0, 0, 0), f32::INFINITY);
+		assert!(!polar(na::Point2::new(0.0, 0.0), na::Point2::new(MIN_SPLIT_LEN / 2.0, 0.0)));
+
+		// Splitting a fill follows the same rule for every edge
+		let square = Triangles {
+			vertices: vec![
+				na::Point2::new(0.0, 0.0),
+				na::Point2::new(1.0, 0.0),
+				na::Point2::new(1.0, 1.0),
+				na::Point2::new(0.0, 1.0),
+			],
+			triangles: vec![(0, 1, 2), (0, 2, 3)],
+		};
+		let fine = square.subdivide(splits_edge(TileCoord::new(0, 0, 0), f32::INFINITY));
+		assert!(fine.triangles.len() > 2);
+		for &(a, b, c) in &fine.triangles {
+			for &(p, q) in &[(a, b), (b, c), (c, a)] {
+				assert!(!world(fine.vertices[p], fine.vertices[q]));
 			}
 		}
+	}
+
+	#[test]
+	fn caps_are_left_off_where_lines_are_cut() {
+		use crate::tessellate::LineCap;
+
+		let line = |coords: &[(i32, i32)]| Feature {
+			id: None,
+			geometry: Geometry::LineString(coords.iter().map(|&(x, y)| Point::new(x, y)).collect()),
+			properties: Default::default(),
+		};
+		let style = LineStyle {
+			cap: LineCap::Square,
+			..LineStyle::default()
+		};
+		let triangles = |coords: &[(i32, i32)]| {
+			let layer = Layer::new("roads", 4096, vec![line(coords)]).with_buffer(64);
+			layer.strokes(&style, |_, _| false).triangles.len()
+		};
+
+		// Two for the line and two for each cap
+		assert_eq!(triangles(&[(100, 100), (200, 100)]), 6);
+		assert_eq!(triangles(&[(-50, 100), (200, 100)]), 4);
+		assert_eq!(triangles(&[(-50, 100), (4150, 100)]), 2);
+		// Ending on the edge isn't the same as being cut there
+		assert_eq!(triangles(&[(0, 100), (200, 100)]), 6);
+	}
+
+	#[test]
+	fn bad_features_are_skipped() {
+		use crate::protos::vector_tile::mod_Tile::{Feature as RawFeature, GeomType, Layer as RawLayer};
+
+		let point = |geometry: Vec<u32>| RawFeature {
+			type_pb: GeomType::POINT,
+			geometry,
+			..RawFeature::default()
+		};
+		let raw = VectorTile {
+			layers: vec![RawLayer {
+				version: 2,
+				name: "places".into(),
+				// The middle feature has a LineTo, which points can't have
+				features: vec![point(vec![9, 50, 34]), point(vec![9, 0, 0, 10, 2, 2]), point(vec![9, 2, 2])],
+				extent: 4096,
+				..RawLayer::default()
+			}],
+		};
+
+		let tile = Tile::from_vector_tile(raw, 0, 0, 0).unwrap();
+		let layer = tile.layer("places").unwrap();
+		assert_eq!(layer.features().len(), 2);
+		assert_eq!(layer.features()[1].geometry, Geometry::Point(Point::new(1, 1)));
+	}
+
+	#[test]
+	fn features_are_kept_as_far_as_the_buffer() {
+		use crate::protos::vector_tile::mod_Tile::{Feature as RawFeature, GeomType, Layer as RawLayer};
+
+		// A point 100 units past the left edge of the tile, and one inside it, in a layer of half the usual extent
+		let raw = || VectorTile {
+			layers: vec![RawLayer {
+				version: 2,
+				name: "places".into(),
+				features: vec![
+					RawFeature {
+						type_pb: GeomType::POINT,
+						geometry: vec![9, 199, 200],
+						..RawFeature::default()
+					},
+					RawFeature {
+						type_pb: GeomType::POINT,
+						geometry: vec![9, 200, 200],
+						..RawFeature::default()
+					},
+				],
+				extent: 2048,
+				..RawLayer::default()
+			}],
+		};
+		let count = |buffer| {
+			let options = VectorOptions {
+				buffer,
+				..VectorOptions::default()
+			};
+			let tile = Tile::from_vector_tile_with_options(raw(), &options, 0, 0, 0).unwrap();
+			let layer = tile.layer("places").unwrap();
+			assert_eq!(layer.buffer(), buffer / 2);
+			layer.features().len()
+		};
+
+		assert_eq!(count(0), 1);
+		assert_eq!(count(VectorOptions::default().buffer), 1);
+		assert_eq!(count(256), 2);
+	}
+
+	#[test]
+	fn lines_are_stroked_in_the_layer_style() {
+		use crate::protos::vector_tile::mod_Tile::{Feature as RawFeature, GeomType, Layer as RawLayer};
+		use crate::tessellate::LineJoin;
+
+		let raw = VectorTile {
+			layers: vec![RawLayer {
+				version: 2,
+				name: "roads".into(),
+				features: vec![RawFeature {
+					type_pb: GeomType::LINESTRING,
+					geometry: vec![9, 200, 200, 10, 200, 0],
+					..RawFeature::default()
+				}],
+				extent: 4096,
+				..RawLayer::default()
+			}],
+		};
+		let line_style = LineStyle {
+			width: 6.0,
+			join: LineJoin::Round,
+			..LineStyle::default()
+		};
+		let options = VectorOptions {
+			line_style,
+			..VectorOptions::default()
+		};
+		let tile = Tile::from_vector_tile_with_options(raw, &options, 0, 0, 0).unwrap();
+
+		let widths = |tile: &Tile| -> Vec<f32> {
+			let extrusions = &tile.layer("roads").unwrap().mesh().extrusions;
+			assert!(!extrusions.is_empty());
+			extrusions.iter().map(|extrusion| extrusion.half_width).collect()
+		};
+		assert!(widths(&tile).iter().all(|&half_width| half_width == 3.0));
 
-		Self { mesh }
+		let child = tile.overzoom(TileCoord::new(0, 0, 0), TileCoord::new(0, 0, 2));
+		assert_eq!(child.layer("roads").unwrap().line_style(), &line_style);
+		assert!(widths(&child).iter().all(|&half_width| half_width == 3.0));
 	}
 }
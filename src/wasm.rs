@@ -6,17 +6,20 @@ mod input;
 pub use glmesh::GlMesh;
 pub use renderer::WebGlRenderer;
 pub use input::HtmlInputs;
+use crate::data::{TileCache, WebTileSource};
 use crate::globe::Globe;
 use crate::scene::{Scene, SceneItem};
 use crate::mesh::Mesh;
 use nalgebra as na;
 
+use futures::future::poll_fn;
 use std::panic;
+use std::task::Poll;
 use std::{cell::RefCell, rc::Rc};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::future_to_promise;
+use wasm_bindgen_futures::spawn_local;
 use web_sys::{self, HtmlElement};
 
 // Imported from JS land
@@ -43,7 +46,7 @@ pub struct Environment {
 pub fn attach(container: &HtmlElement, token: &str) -> Environment {
 	panic::set_hook(Box::new(console_error_panic_hook::hook));
 
-	let globe = Rc::new(RefCell::new(Globe::new(token)));
+	let globe = Rc::new(RefCell::new(Globe::new(Box::new(TileCache::in_memory(WebTileSource::mapbox(token))))));
 
 	let mut env = Environment {
 		scene: Rc::new(RefCell::new(Scene::new(globe.clone()))),
@@ -59,23 +62,26 @@ pub fn attach(container: &HtmlElement, token: &str) -> Environment {
 		inputs.attach(container);
 	}
 
-	// Run on next tick
-	let _tile_promise = future_to_promise({
-		let renderer = env.renderer.clone();
-		let scene = env.scene.clone();
+	// Load tiles in the background as the scene queues them. The globe is only
+	// borrowed while polling so the scene can use it in between
+	spawn_local({
+		let globe = globe.clone();
 
 		async move {
-			let mut globe = globe.borrow_mut();
-			let zoom = 2;
-			let n = 2_i32.pow(zoom);
-
-			for y in 0..n {
-				for x in 0..n {
-					globe.queue_tile(x, y, zoom as i32);
-					globe.update().await;
+			loop {
+				let (coord, result) = poll_fn(|cx| match globe.try_borrow_mut() {
+					Ok(mut globe) => globe.poll_update(cx),
+					Err(_) => {
+						// Globe is busy, try again on the next tick
+						cx.waker().wake_by_ref();
+						Poll::Pending
+					}
+				})
+				.await;
+				if let Err(err) = result {
+					log(&format!("Failed to load tile {}: {}", coord, err));
 				}
 			}
-			Ok(true.into())
 		}
 	});
 
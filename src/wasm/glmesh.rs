@@ -1,20 +1,36 @@
-use crate::mesh::Mesh;
+use crate::mesh::{Mesh, Texture};
 use js_sys::{Float32Array, Uint32Array};
 use nalgebra as na;
-use web_sys::{WebGlBuffer, WebGlRenderingContext};
+use std::rc::Rc;
+use web_sys::{WebGlBuffer, WebGlRenderingContext, WebGlTexture};
 
 pub struct GlMesh {
 	pub(super) vertices: Vec<f32>,
 	pub(super) indices: Vec<u32>,
+	pub(super) uvs: Vec<f32>,
+	pub(super) texture: Option<Rc<Texture>>,
+	pub(super) extrusions: Vec<f32>,
 	pub(super) vertex_buffer: Option<WebGlBuffer>,
 	pub(super) index_buffer: Option<WebGlBuffer>,
+	pub(super) uv_buffer: Option<WebGlBuffer>,
+	pub(super) gl_texture: Option<WebGlTexture>,
+	pub(super) extrusion_buffer: Option<WebGlBuffer>,
 	pub(super) transform: na::Matrix4<f32>,
 	pub(super) count: u32,
+	pub(super) version: usize,
 }
 
 impl From<&Mesh> for GlMesh {
 	fn from(mesh: &Mesh) -> Self {
-		Self::new(mesh.vertices_as_vec(), mesh.triangles_as_vec())
+		let mut gl_mesh = Self::new(mesh.vertices_as_vec(), mesh.triangles_as_vec());
+		if let Some(texture) = &mesh.texture {
+			gl_mesh.uvs = mesh.uvs_as_vec();
+			gl_mesh.texture = Some(texture.clone());
+		}
+		if mesh.has_lines() {
+			gl_mesh.extrusions = mesh.extrusions_as_vec();
+		}
+		gl_mesh
 	}
 }
 
@@ -23,10 +39,17 @@ impl GlMesh {
 		Self {
 			vertex_buffer: None,
 			index_buffer: None,
+			uv_buffer: None,
+			gl_texture: None,
+			extrusion_buffer: None,
 			vertices,
 			indices,
+			uvs: vec![],
+			texture: None,
+			extrusions: vec![],
 			transform: na::Matrix4::identity(),
 			count: 0,
+			version: 0,
 		}
 	}
 
@@ -34,9 +57,39 @@ impl GlMesh {
 		self.index_buffer.is_some()
 	}
 
+	pub fn is_textured(&self) -> bool {
+		self.texture.is_some()
+	}
+
+	pub fn has_lines(&self) -> bool {
+		!self.extrusions.is_empty()
+	}
+
 	pub fn upload(&mut self, gl: &WebGlRenderingContext) {
 		self.upload_vertices(gl);
 		self.upload_indices(gl);
+		if self.is_textured() {
+			self.upload_uvs(gl);
+			self.upload_texture(gl);
+		}
+		if self.has_lines() {
+			self.upload_extrusions(gl);
+		}
+	}
+
+	/// Free the GPU buffers and texture
+	pub fn release(&mut self, gl: &WebGlRenderingContext) {
+		gl.delete_buffer(self.vertex_buffer.as_ref());
+		gl.delete_buffer(self.index_buffer.as_ref());
+		gl.delete_buffer(self.uv_buffer.as_ref());
+		gl.delete_texture(self.gl_texture.as_ref());
+		gl.delete_buffer(self.extrusion_buffer.as_ref());
+		self.vertex_buffer = None;
+		self.index_buffer = None;
+		self.uv_buffer = None;
+		self.gl_texture = None;
+		self.extrusion_buffer = None;
+		self.count = 0;
 	}
 
 	pub fn bind(&self, gl: &WebGlRenderingContext) {
@@ -47,6 +100,17 @@ impl GlMesh {
 		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, self.vertex_buffer.as_ref());
 	}
 
+	/// Bind the texture coordinates to `ARRAY_BUFFER` and the texture to the active texture unit
+	pub fn bind_texture(&self, gl: &WebGlRenderingContext) {
+		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, self.uv_buffer.as_ref());
+		gl.bind_texture(WebGlRenderingContext::TEXTURE_2D, self.gl_texture.as_ref());
+	}
+
+	/// Bind the line extrusions to `ARRAY_BUFFER`
+	pub fn bind_extrusions(&self, gl: &WebGlRenderingContext) {
+		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, self.extrusion_buffer.as_ref());
+	}
+
 	fn upload_vertices(&mut self, gl: &WebGlRenderingContext) {
 		let vertex_buffer = gl.create_buffer().unwrap();
 		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&vertex_buffer));
@@ -71,4 +135,61 @@ impl GlMesh {
 
 		self.index_buffer = Some(index_buffer);
 	}
+
+	fn upload_uvs(&mut self, gl: &WebGlRenderingContext) {
+		let uv_buffer = gl.create_buffer().unwrap();
+		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&uv_buffer));
+		gl.buffer_data_with_opt_array_buffer(
+			WebGlRenderingContext::ARRAY_BUFFER,
+			Some(&Float32Array::from(self.uvs.as_slice()).buffer()),
+			WebGlRenderingContext::STATIC_DRAW,
+		);
+		self.uv_buffer = Some(uv_buffer);
+	}
+
+	fn upload_extrusions(&mut self, gl: &WebGlRenderingContext) {
+		let extrusion_buffer = gl.create_buffer().unwrap();
+		gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&extrusion_buffer));
+		gl.buffer_data_with_opt_array_buffer(
+			WebGlRenderingContext::ARRAY_BUFFER,
+			Some(&Float32Array::from(self.extrusions.as_slice()).buffer()),
+			WebGlRenderingContext::STATIC_DRAW,
+		);
+		self.extrusion_buffer = Some(extrusion_buffer);
+	}
+
+	fn upload_texture(&mut self, gl: &WebGlRenderingContext) {
+		let texture = match &self.texture {
+			Some(texture) => texture,
+			None => return,
+		};
+
+		let gl_texture = gl.create_texture().unwrap();
+		gl.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&gl_texture));
+		gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+			WebGlRenderingContext::TEXTURE_2D,
+			0,
+			WebGlRenderingContext::RGBA as i32,
+			texture.width as i32,
+			texture.height as i32,
+			0,
+			WebGlRenderingContext::RGBA,
+			WebGlRenderingContext::UNSIGNED_BYTE,
+			Some(texture.pixels.as_slice()),
+		)
+		.unwrap();
+
+		// No mipmaps and clamped edges, so tiles that aren't a power of two in size still work in WebGL 1
+		let params = [
+			(WebGlRenderingContext::TEXTURE_MIN_FILTER, WebGlRenderingContext::LINEAR),
+			(WebGlRenderingContext::TEXTURE_MAG_FILTER, WebGlRenderingContext::LINEAR),
+			(WebGlRenderingContext::TEXTURE_WRAP_S, WebGlRenderingContext::CLAMP_TO_EDGE),
+			(WebGlRenderingContext::TEXTURE_WRAP_T, WebGlRenderingContext::CLAMP_TO_EDGE),
+		];
+		for &(name, value) in &params {
+			gl.tex_parameteri(WebGlRenderingContext::TEXTURE_2D, name, value as i32);
+		}
+
+		self.gl_texture = Some(gl_texture);
+	}
 }
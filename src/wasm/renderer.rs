@@ -14,23 +14,59 @@ use web_sys::{
 static VERTEX_GLSL: &'static str = "
 	uniform mat4 view_proj;
 	uniform mat4 model;
+	uniform vec2 viewport;
+	uniform float pixel_ratio;
 	attribute vec3 position;
+	attribute vec2 uv;
+	attribute vec3 extrusion;
+	attribute vec2 edge;
 	varying vec4 color;
+	varying vec2 tex_coord;
+	varying vec2 line_edge;
 
 	void main(void) {
 		mat4 mvp = view_proj * model;
-		gl_Position = mvp * vec4(position, 1.0);
-		color = (vec4(position, 1.0) * 0.5 + 0.5) * (2.0 - (gl_Position.z / 1.5));
+		vec4 clip = mvp * vec4(position, 1.0);
+		color = (vec4(position, 1.0) * 0.5 + 0.5) * (2.0 - (clip.z / 1.5));
+		// Only the edges of lines are see-through
+		color.a = 1.0;
+
+		// Push line vertices out across the screen, turning the extrusion into a direction in pixels
+		float len = length(extrusion);
+		if (len > 0.0) {
+			vec4 along = mvp * vec4(extrusion / len, 0.0);
+			vec2 dir = (along.xy * clip.w - clip.xy * along.w) * viewport;
+			if (length(dir) > 0.0) {
+				clip.xy += normalize(dir) * len * pixel_ratio * 2.0 / viewport * clip.w;
+			}
+		}
+		gl_Position = clip;
+		tex_coord = uv;
+		line_edge = vec2(edge.x, edge.y * pixel_ratio);
 	}
 ";
 
 static FRAGMENT_GLSL: &'static str = "
 	precision mediump float;
 
+	uniform bool textured;
+	uniform sampler2D image;
 	varying vec4 color;
+	varying vec2 tex_coord;
+	// Distance across the line from -1.0 to 1.0, and half its width in pixels
+	varying vec2 line_edge;
 
 	void main(void) {
-		gl_FragColor = color;
+		if (textured && tex_coord.x >= 0.0) {
+			gl_FragColor = texture2D(image, tex_coord);
+		} else {
+			gl_FragColor = color;
+		}
+
+		// Fade out over the last pixel at the sides of lines
+		if (line_edge.y > 0.0) {
+			gl_FragColor.a *= clamp((1.0 - abs(line_edge.x)) * line_edge.y + 0.5, 0.0, 1.0);
+		}
 	}
 ";
 
@@ -115,6 +151,9 @@ impl WebGlRenderer {
 			// Enable 32bit index buffers
 			gl.get_extension("OES_element_index_uint").unwrap();
 			gl.enable(WebGlRenderingContext::DEPTH_TEST);
+			gl.active_texture(WebGlRenderingContext::TEXTURE0);
+			// Blend the smoothed edges of lines, switched on only for meshes that have them
+			gl.blend_func(WebGlRenderingContext::SRC_ALPHA, WebGlRenderingContext::ONE_MINUS_SRC_ALPHA);
 
 			gl.viewport(0, 0, self.width, self.height);
 			let program = gl.create_program().unwrap();
@@ -160,14 +199,32 @@ impl WebGlRenderer {
 			let vp_uniform = gl.get_uniform_location(program.unwrap(), "view_proj");
 			gl.uniform_matrix4fv_with_f32_array(vp_uniform.as_ref(), false, vp.as_slice());
 
+			// Line widths are in CSS pixels, which can be bigger than the canvas's own pixels
+			let pixel_ratio = match &self.element {
+				Some(el) if el.client_width() > 0 => el.width() as f32 / el.client_width() as f32,
+				_ => 1.0,
+			};
+			let viewport_uniform = gl.get_uniform_location(program.unwrap(), "viewport");
+			gl.uniform2f(viewport_uniform.as_ref(), self.width as f32, self.height as f32);
+			let pixel_ratio_uniform = gl.get_uniform_location(program.unwrap(), "pixel_ratio");
+			gl.uniform1f(pixel_ratio_uniform.as_ref(), pixel_ratio);
+
 			for (i, item) in scene.items().iter().enumerate() {
 				if i > self.meshes.len() {
 					panic!("We lost a mesh");
 				}
 				if i == self.meshes.len() {
 					let mut mesh = GlMesh::from(&item.mesh);
+					mesh.version = item.version;
 					mesh.upload(gl);
 					self.meshes.push(mesh);
+				} else if self.meshes[i].version != item.version {
+					// The scene replaced this item, swap in the new mesh
+					self.meshes[i].release(gl);
+					let mut mesh = GlMesh::from(&item.mesh);
+					mesh.version = item.version;
+					mesh.upload(gl);
+					self.meshes[i] = mesh;
 				}
 				let mesh = &self.meshes[i];
 				let transform = &item.transform;
@@ -183,6 +240,52 @@ impl WebGlRenderer {
 				let position_attrib = gl.get_attrib_location(program.unwrap(), "position") as u32;
 				gl.vertex_attrib_pointer_with_f64(position_attrib, 3, WebGlRenderingContext::FLOAT, false, 0, 0.0);
 
+				// Raster tiles sample their image, everything else is coloured by position
+				let textured_uniform = gl.get_uniform_location(program.unwrap(), "textured");
+				let uv_attrib = gl.get_attrib_location(program.unwrap(), "uv") as u32;
+				if mesh.is_textured() {
+					mesh.bind_texture(gl);
+					gl.enable_vertex_attrib_array(uv_attrib);
+					gl.vertex_attrib_pointer_with_f64(uv_attrib, 2, WebGlRenderingContext::FLOAT, false, 0, 0.0);
+					let image_uniform = gl.get_uniform_location(program.unwrap(), "image");
+					gl.uniform1i(image_uniform.as_ref(), 0);
+					gl.uniform1i(textured_uniform.as_ref(), 1);
+				} else {
+					gl.disable_vertex_attrib_array(uv_attrib);
+					gl.uniform1i(textured_uniform.as_ref(), 0);
+				}
+
+				// Lines are widened by the shader, anything else has no extrusion
+				let extrusion_attrib = gl.get_attrib_location(program.unwrap(), "extrusion") as u32;
+				let edge_attrib = gl.get_attrib_location(program.unwrap(), "edge") as u32;
+				if mesh.has_lines() {
+					gl.enable(WebGlRenderingContext::BLEND);
+					mesh.bind_extrusions(gl);
+					let stride = 5 * std::mem::size_of::<f32>() as i32;
+					gl.enable_vertex_attrib_array(extrusion_attrib);
+					gl.vertex_attrib_pointer_with_f64(
+						extrusion_attrib,
+						3,
+						WebGlRenderingContext::FLOAT,
+						false,
+						stride,
+						0.0,
+					);
+					gl.enable_vertex_attrib_array(edge_attrib);
+					gl.vertex_attrib_pointer_with_f64(
+						edge_attrib,
+						2,
+						WebGlRenderingContext::FLOAT,
+						false,
+						stride,
+						12.0,
+					);
+				} else {
+					gl.disable(WebGlRenderingContext::BLEND);
+					gl.disable_vertex_attrib_array(extrusion_attrib);
+					gl.disable_vertex_attrib_array(edge_attrib);
+				}
+
 				gl.draw_elements_with_i32(
 					WebGlRenderingContext::TRIANGLES,
 					mesh.count as i32,